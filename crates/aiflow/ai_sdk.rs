@@ -0,0 +1,467 @@
+//! Encoder for the Vercel AI SDK UI message stream protocol.
+//!
+//! [`ui_message_stream`] turns a stream of [`Event`]s into the server-sent
+//! events expected by the AI SDK's `useChat`, so a frontend can consume a Rust
+//! backend directly. Responses must set the [`HEADER`] to mark the protocol.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use futures::{Stream, StreamExt as _};
+use genawaiter::sync::Gen;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{Error, Event, FinishReason, message, util::parse_incomplete_json};
+
+/// Header that marks a response as a UI message stream.
+pub const HEADER: (&str, &str) = ("x-vercel-ai-ui-message-stream", "v1");
+
+/// A chunk of the UI message stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "kebab-case",
+    rename_all_fields = "camelCase"
+)]
+#[non_exhaustive]
+pub enum Chunk {
+    /// A new message was started.
+    Start {
+        /// Identifier of the message.
+        message_id: String,
+    },
+    /// A step, i.e. a model call and its tool calls, was started.
+    StartStep,
+    /// The step was completed.
+    FinishStep,
+    /// A text part was started.
+    TextStart {
+        /// Identifier of the text part.
+        id: String,
+    },
+    /// Text was appended to a text part.
+    TextDelta {
+        /// Identifier of the text part.
+        id: String,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// A text part was completed.
+    TextEnd {
+        /// Identifier of the text part.
+        id: String,
+    },
+    /// A reasoning part was started.
+    ReasoningStart {
+        /// Identifier of the reasoning part.
+        id: String,
+    },
+    /// Text was appended to a reasoning part.
+    ReasoningDelta {
+        /// Identifier of the reasoning part.
+        id: String,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// A reasoning part was completed.
+    ReasoningEnd {
+        /// Identifier of the reasoning part.
+        id: String,
+    },
+    /// A tool call was started.
+    ToolInputStart {
+        /// Identifier of the tool call.
+        tool_call_id: String,
+        /// Name of the tool being called.
+        tool_name: String,
+    },
+    /// Arguments were appended to a tool call.
+    ToolInputDelta {
+        /// Identifier of the tool call.
+        tool_call_id: String,
+        /// The raw JSON fragment that was appended.
+        input_text_delta: String,
+    },
+    /// The arguments of a tool call are complete.
+    ToolInputAvailable {
+        /// Identifier of the tool call.
+        tool_call_id: String,
+        /// Name of the tool being called.
+        tool_name: String,
+        /// The arguments of the tool call.
+        input: Value,
+    },
+    /// A tool call produced a result.
+    ToolOutputAvailable {
+        /// Identifier of the tool call.
+        tool_call_id: String,
+        /// The result of the tool call.
+        output: Value,
+    },
+    /// The stream failed.
+    Error {
+        /// Description of the error.
+        error_text: String,
+    },
+    /// The message is complete.
+    Finish,
+    /// The generation was cancelled.
+    Abort,
+}
+
+impl Chunk {
+    /// Returns the chunk as a server-sent event.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the chunk cannot be serialized, which cannot happen for
+    /// JSON values.
+    #[must_use]
+    pub fn to_sse(&self) -> String {
+        format!(
+            "data: {}\n\n",
+            serde_json::to_string(self).expect("chunk to serialize")
+        )
+    }
+}
+
+/// A tool call whose identifier is needed for later chunks.
+#[derive(Debug, Clone)]
+struct ToolCall {
+    id: String,
+    name: String,
+    args: String,
+}
+
+/// Translates [`Event`]s into [`Chunk`]s.
+///
+/// Text, reasoning and tool input parts are closed as soon as an event for
+/// another part arrives.
+#[derive(Debug, Clone, Default)]
+pub struct Encoder {
+    open: Option<usize>,
+    reasoning: BTreeSet<usize>,
+    tool_calls: BTreeMap<usize, ToolCall>,
+}
+
+impl Encoder {
+    /// Returns the chunks for the given event.
+    #[must_use]
+    pub fn encode(&mut self, event: &Event) -> Vec<Chunk> {
+        let mut chunks = Vec::new();
+        match *event {
+            Event::MessageStarted { ref id, .. } => {
+                self.open = None;
+                self.reasoning.clear();
+                self.tool_calls.clear();
+                chunks.push(Chunk::Start {
+                    message_id: id.clone(),
+                });
+            }
+            Event::TextDelta {
+                part_index,
+                ref delta,
+            } => {
+                if self.open != Some(part_index) {
+                    chunks.extend(self.close());
+                    self.open = Some(part_index);
+                    chunks.push(Chunk::TextStart {
+                        id: text_id(part_index),
+                    });
+                }
+                chunks.push(Chunk::TextDelta {
+                    id: text_id(part_index),
+                    delta: delta.clone(),
+                });
+            }
+            Event::ReasoningStarted { part_index, .. } => {
+                chunks.extend(self.close());
+                self.open = Some(part_index);
+                self.reasoning.insert(part_index);
+                chunks.push(Chunk::ReasoningStart {
+                    id: reasoning_id(part_index),
+                });
+            }
+            Event::ReasoningDelta {
+                part_index,
+                ref delta,
+            } => {
+                chunks.push(Chunk::ReasoningDelta {
+                    id: reasoning_id(part_index),
+                    delta: delta.clone(),
+                });
+            }
+            Event::ReasoningDone { part_index, .. } => {
+                if self.open == Some(part_index) {
+                    chunks.extend(self.close());
+                }
+            }
+            Event::ToolCallStarted {
+                part_index,
+                ref id,
+                ref name,
+            } => {
+                chunks.extend(self.close());
+                self.open = Some(part_index);
+                self.tool_calls.insert(
+                    part_index,
+                    ToolCall {
+                        id: id.clone(),
+                        name: name.clone(),
+                        args: String::new(),
+                    },
+                );
+                chunks.push(Chunk::ToolInputStart {
+                    tool_call_id: id.clone(),
+                    tool_name: name.clone(),
+                });
+            }
+            Event::ToolArgsDelta {
+                part_index,
+                ref delta,
+            } => {
+                if let Some(tool_call) = self.tool_calls.get_mut(&part_index) {
+                    tool_call.args.push_str(delta);
+                    chunks.push(Chunk::ToolInputDelta {
+                        tool_call_id: tool_call.id.clone(),
+                        input_text_delta: delta.clone(),
+                    });
+                }
+            }
+            Event::ToolResult {
+                part_index,
+                ref result,
+            } => {
+                chunks.extend(self.close());
+                if let Some(tool_call) = self.tool_calls.get(&part_index) {
+                    // Content is sent as its parts.
+                    chunks.push(Chunk::ToolOutputAvailable {
+                        tool_call_id: tool_call.id.clone(),
+                        output: match *result {
+                            message::ToolOutput::Json { ref value } => value.clone(),
+                            message::ToolOutput::Content { ref content } => json!(content),
+                        },
+                    });
+                }
+            }
+            Event::StepStarted => chunks.push(Chunk::StartStep),
+            Event::StepFinished => {
+                chunks.extend(self.close());
+                chunks.push(Chunk::FinishStep);
+            }
+            Event::Usage(_) | Event::Retry { .. } | Event::Fallback { .. } => {}
+            Event::Finished { reason } => {
+                chunks.extend(self.close());
+                chunks.push(if reason == FinishReason::Cancelled {
+                    Chunk::Abort
+                } else {
+                    Chunk::Finish
+                });
+            }
+        }
+        chunks
+    }
+
+    /// Closes the open part, if any.
+    fn close(&mut self) -> Option<Chunk> {
+        let part_index = self.open.take()?;
+        if self.reasoning.contains(&part_index) {
+            return Some(Chunk::ReasoningEnd {
+                id: reasoning_id(part_index),
+            });
+        }
+        Some(self.tool_calls.get(&part_index).map_or_else(
+            || Chunk::TextEnd {
+                id: text_id(part_index),
+            },
+            |tool_call| Chunk::ToolInputAvailable {
+                tool_call_id: tool_call.id.clone(),
+                tool_name: tool_call.name.clone(),
+                input: match parse_incomplete_json(&tool_call.args) {
+                    Ok(Value::Null) | Err(_) => Value::Object(serde_json::Map::new()),
+                    Ok(input) => input,
+                },
+            },
+        ))
+    }
+}
+
+/// Returns the identifier of the text part at the given index.
+fn text_id(part_index: usize) -> String {
+    format!("text-{part_index}")
+}
+
+/// Returns the identifier of the reasoning part at the given index.
+fn reasoning_id(part_index: usize) -> String {
+    format!("reasoning-{part_index}")
+}
+
+/// Encodes a stream of events as a UI message stream of server-sent events.
+///
+/// Errors are sent as an error chunk and end the stream. The stream is always
+/// terminated with `[DONE]`.
+pub fn ui_message_stream<S>(events: S) -> impl Stream<Item = String>
+where
+    S: Stream<Item = Result<Event, Error>>,
+{
+    Gen::new(|co| async move {
+        let mut events = core::pin::pin!(events);
+        let mut encoder = Encoder::default();
+        while let Some(result) = events.next().await {
+            match result {
+                Ok(event) => {
+                    for chunk in encoder.encode(&event) {
+                        co.yield_(chunk.to_sse()).await;
+                    }
+                }
+                Err(error) => {
+                    let chunk = Chunk::Error {
+                        error_text: error.to_string(),
+                    };
+                    co.yield_(chunk.to_sse()).await;
+                    break;
+                }
+            }
+        }
+        co.yield_(String::from("data: [DONE]\n\n")).await;
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use serde_json::json;
+
+    use super::*;
+    use crate::Model;
+
+    #[tokio::test]
+    async fn encodes_events() {
+        let events = [
+            Event::MessageStarted {
+                id: String::from("message"),
+                model: Model::Gpt4_1,
+            },
+            Event::StepStarted,
+            Event::TextDelta {
+                part_index: 0,
+                delta: String::from("Hi"),
+            },
+            Event::ToolCallStarted {
+                part_index: 1,
+                id: String::from("call"),
+                name: String::from("add"),
+            },
+            Event::ToolArgsDelta {
+                part_index: 1,
+                delta: String::from(r#"{"left":1,"right":2}"#),
+            },
+            Event::ToolResult {
+                part_index: 1,
+                result: json!(3_i32).into(),
+            },
+            Event::StepFinished,
+            Event::StepStarted,
+            Event::TextDelta {
+                part_index: 2,
+                delta: String::from("3"),
+            },
+            Event::StepFinished,
+            Event::Finished {
+                reason: FinishReason::Stop,
+            },
+        ];
+        let output: Vec<String> = ui_message_stream(stream::iter(events.into_iter().map(Ok)))
+            .collect()
+            .await;
+
+        assert_eq!(
+            output,
+            [
+                r#"{"type":"start","messageId":"message"}"#,
+                r#"{"type":"start-step"}"#,
+                r#"{"type":"text-start","id":"text-0"}"#,
+                r#"{"type":"text-delta","id":"text-0","delta":"Hi"}"#,
+                r#"{"type":"text-end","id":"text-0"}"#,
+                r#"{"type":"tool-input-start","toolCallId":"call","toolName":"add"}"#,
+                r#"{"type":"tool-input-delta","toolCallId":"call","inputTextDelta":"{\"left\":1,\"right\":2}"}"#,
+                r#"{"type":"tool-input-available","toolCallId":"call","toolName":"add","input":{"left":1,"right":2}}"#,
+                r#"{"type":"tool-output-available","toolCallId":"call","output":3}"#,
+                r#"{"type":"finish-step"}"#,
+                r#"{"type":"start-step"}"#,
+                r#"{"type":"text-start","id":"text-2"}"#,
+                r#"{"type":"text-delta","id":"text-2","delta":"3"}"#,
+                r#"{"type":"text-end","id":"text-2"}"#,
+                r#"{"type":"finish-step"}"#,
+                r#"{"type":"finish"}"#,
+                "[DONE]",
+            ]
+            .map(|data| format!("data: {data}\n\n"))
+        );
+    }
+
+    #[test]
+    fn encodes_reasoning() {
+        let mut encoder = Encoder::default();
+        let chunks: Vec<Chunk> = [
+            Event::ReasoningStarted {
+                part_index: 0,
+                id: None,
+                provider: Some(String::from("anthropic")),
+            },
+            Event::ReasoningDelta {
+                part_index: 0,
+                delta: String::from("Adding."),
+            },
+            Event::ReasoningDone {
+                part_index: 0,
+                encrypted_content: None,
+            },
+            Event::TextDelta {
+                part_index: 1,
+                delta: String::from("3"),
+            },
+        ]
+        .iter()
+        .flat_map(|event| encoder.encode(event))
+        .collect();
+
+        assert_eq!(
+            chunks,
+            [
+                Chunk::ReasoningStart {
+                    id: String::from("reasoning-0"),
+                },
+                Chunk::ReasoningDelta {
+                    id: String::from("reasoning-0"),
+                    delta: String::from("Adding."),
+                },
+                Chunk::ReasoningEnd {
+                    id: String::from("reasoning-0"),
+                },
+                Chunk::TextStart {
+                    id: String::from("text-1"),
+                },
+                Chunk::TextDelta {
+                    id: String::from("text-1"),
+                    delta: String::from("3"),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn encodes_errors() {
+        let events = stream::iter([Err(Error::Other(String::from("Failed to create stream")))]);
+        let output: Vec<String> = ui_message_stream(events).collect().await;
+        assert_eq!(
+            output,
+            [
+                String::from(
+                    "data: {\"type\":\"error\",\"errorText\":\"Failed to create stream\"}\n\n"
+                ),
+                String::from("data: [DONE]\n\n"),
+            ]
+        );
+    }
+}
@@ -4,35 +4,44 @@ extern crate alloc;
 
 mod util;
 
+pub mod ai_sdk;
+pub mod anthropic;
+pub mod error;
+pub use error::{ApiError, ApiErrorKind, BudgetExceeded, Error, PricingError};
+pub mod event;
+pub use event::{Event, FinishReason};
 pub mod message;
-pub mod openai;
 pub use message::Message;
+pub mod object;
+pub mod openai;
+mod pricing;
+pub use pricing::{Pricing, PricingTable};
+pub mod provider;
+#[cfg(feature = "server")]
+pub mod server;
+pub use provider::Provider;
 pub mod tool;
-use openai_responses::{
-    StreamError,
-    types::{Input, Request},
-};
 use tokio::{
     sync::{Mutex, OwnedMutexGuard},
     task::JoinSet,
+    time::Instant,
 };
+pub use tokio_util::sync::CancellationToken;
 pub use tool::{Tool, ToolBuilder};
 
-use assert2::let_assert;
-use async_openai::types::{
-    ChatCompletionStreamOptions, ChatCompletionToolChoiceOption, CreateChatCompletionRequestArgs,
-};
-use bigdecimal::{BigDecimal, FromPrimitive as _};
-use genawaiter::sync::Gen;
+use bigdecimal::{BigDecimal, ToPrimitive as _};
+use genawaiter::sync::{Co, Gen};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 
-use alloc::collections::BTreeMap;
-use alloc::collections::btree_map;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
 use core::fmt::{self, Display};
-use futures::{FutureExt as _, Stream, StreamExt as _};
-use util::parse_incomplete_json;
+use core::pin::pin;
+use core::time::Duration;
+use futures::{Stream, StreamExt as _};
+use rustc_hash::FxHashMap;
+use std::time::SystemTime;
 use uuid::Uuid;
 
 /// Tracks token usage for a session, including cached, input, and output tokens.
@@ -44,20 +53,304 @@ pub struct Usage {
     pub input_tokens: BigDecimal,
     /// Number of output tokens.
     pub output_tokens: BigDecimal,
+    /// Number of output tokens spent on reasoning, included in `output_tokens`.
+    #[serde(default)]
+    pub reasoning_tokens: BigDecimal,
+    /// Number of audio input tokens, included in `input_tokens`.
+    #[serde(default)]
+    pub audio_input_tokens: BigDecimal,
+    /// Number of audio output tokens, included in `output_tokens`.
+    #[serde(default)]
+    pub audio_output_tokens: BigDecimal,
+}
+
+impl Usage {
+    /// Returns the total number of tokens, i.e. cached input, input and output tokens.
+    #[must_use]
+    pub fn total_tokens(&self) -> BigDecimal {
+        &self.cached_input_tokens + &self.input_tokens + &self.output_tokens
+    }
+
+    /// Adds the tokens of another usage to this one.
+    pub fn accumulate(&mut self, other: &Self) {
+        self.cached_input_tokens += &other.cached_input_tokens;
+        self.input_tokens += &other.input_tokens;
+        self.output_tokens += &other.output_tokens;
+        self.reasoning_tokens += &other.reasoning_tokens;
+        self.audio_input_tokens += &other.audio_input_tokens;
+        self.audio_output_tokens += &other.audio_output_tokens;
+    }
 }
 
 /// Represents an AI session, tracking the cursor and cost.
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Session {
-    /// Cursor for the previous response, if any.
-    pub cursor: Option<String>,
+    /// Cursor for the previous response, if any. Set it to `None` to send the
+    /// whole thread again.
+    pub cursor: Option<Cursor>,
     /// Total cost of the session.
     pub cost: BigDecimal,
+    /// Total token usage of the session.
+    #[serde(default)]
+    pub usage: Usage,
+    /// Limits on the cost and tokens of the session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget: Option<Budget>,
+    /// Usage and cost of the session per model, indexed by model name.
+    #[serde(default)]
+    pub models: BTreeMap<String, ModelUsage>,
+    /// Record of every model call of the session, in order, including calls
+    /// that failed or were cancelled.
+    #[serde(default)]
+    pub steps: Vec<StepRecord>,
+    /// Number of tool calls of the session per tool name.
+    #[serde(default)]
+    pub tool_calls: BTreeMap<String, u32>,
+}
+
+/// Server-side conversation state that a provider can continue from.
+///
+/// The cursor marks how much of the thread the provider has already seen: all
+/// messages up to the one with `message_id`, except for the results of tool
+/// calls in its parts from `part_index` on. Providers that keep state only send
+/// the rest of the thread. If the message is not part of the thread anymore,
+/// the whole thread is sent instead.
+///
+/// Sessions of earlier versions stored only the response id. Such cursors are
+/// read with an empty message id, so the next call sends the whole thread.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Cursor {
+    /// Identifier of the provider's response, e.g. OpenAI's `previous_response_id`.
+    pub response_id: String,
+    /// Identifier of the message the response was written to.
+    pub message_id: String,
+    /// Index of the first part of the message that the response produced.
+    pub part_index: usize,
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        /// The fields of a cursor.
+        #[derive(Deserialize)]
+        struct Fields {
+            response_id: String,
+            message_id: String,
+            part_index: usize,
+        }
+
+        /// A cursor, or the bare response id of earlier versions.
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Cursor(Fields),
+            ResponseId(String),
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Cursor(Fields {
+                response_id,
+                message_id,
+                part_index,
+            }) => Self {
+                response_id,
+                message_id,
+                part_index,
+            },
+            Stored::ResponseId(response_id) => Self {
+                response_id,
+                message_id: String::new(),
+                part_index: 0,
+            },
+        })
+    }
+}
+
+/// Usage and cost of a single model in a [`Session`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModelUsage {
+    /// Number of completed calls to the model.
+    pub calls: u32,
+    /// Total token usage of the model.
+    pub usage: Usage,
+    /// Total cost of the model.
+    pub cost: BigDecimal,
+}
+
+/// Record of a single model call in a [`Session`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepRecord {
+    /// The model that served the call.
+    pub model: Model,
+    /// When the call was started.
+    pub started_at: SystemTime,
+    /// Time until the first event of the response, including retries.
+    pub latency: Option<Duration>,
+    /// Time until the response was complete.
+    pub duration: Duration,
+    /// Token usage of the call.
+    pub usage: Usage,
+    /// Cost of the call.
+    pub cost: BigDecimal,
+    /// Number of tools the model called.
+    pub tool_calls: u32,
+}
+
+/// The result of a generation that ran to completion, e.g. with [`generate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    /// The final assistant message, including all tool calls and results.
+    pub message: Message,
+    /// The model calls of the generation, in order.
+    pub steps: Vec<StepRecord>,
+    /// Token usage of the generation.
+    pub usage: Usage,
+    /// Cost of the generation.
+    pub cost: BigDecimal,
+    /// Why the generation finished.
+    pub finish_reason: FinishReason,
+}
+
+impl Generation {
+    /// Returns the text that the model wrote after its last tool call, i.e.
+    /// its final answer.
+    #[must_use]
+    pub fn text(&self) -> String {
+        self.message
+            .parts
+            .iter()
+            .rev()
+            .take_while(|part| !matches!(**part, message::Part::Tool(_)))
+            .filter_map(|part| match *part {
+                message::Part::Text(ref text_part) => Some(text_part.text.as_str()),
+                message::Part::Image(_)
+                | message::Part::File(_)
+                | message::Part::Tool(_)
+                | message::Part::Reasoning(_)
+                | message::Part::Error(_) => None,
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect()
+    }
+}
+
+/// Limits on the cost and tokens of a [`Session`].
+///
+/// The budget is checked before every model call. Once it is exhausted, the
+/// generation fails with [`Error::BudgetExceeded`].
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Budget {
+    /// Maximum cost of the session.
+    pub max_cost: Option<BigDecimal>,
+    /// Maximum number of tokens of the session, see [`Usage::total_tokens`].
+    pub max_tokens: Option<u64>,
+    /// Whether to roughly estimate the input tokens of a model call before
+    /// starting it, so that calls that would clearly exceed the budget are not
+    /// made.
+    ///
+    /// The estimate is a heuristic, not a token count: it assumes four bytes
+    /// of text per token and leaves out the JSON around the text and the
+    /// content of images and files, so that it errs low. Calls that exceed
+    /// the budget can still be made. Set a
+    /// [`token_counter`](Self::token_counter) for exact counts.
+    pub preflight: bool,
+    /// Counts the input tokens of a model call before starting it, e.g. with
+    /// the model's tokenizer or a counting endpoint of the provider. Calls
+    /// whose input would exceed the budget are never made. Takes precedence
+    /// over [`preflight`](Self::preflight).
+    #[serde(skip)]
+    pub token_counter: Option<TokenCounter>,
+}
+
+/// Counts the input tokens of a model call for a [`Budget`].
+///
+/// Counters are not serialized. Two counters are equal if they share the same
+/// closure.
+#[derive(Clone)]
+pub struct TokenCounter(Arc<CountTokens>);
+
+/// A closure that counts the input tokens of a thread and tools.
+type CountTokens = dyn Fn(&[Message], &tool::Set) -> u64 + Send + Sync;
+
+impl TokenCounter {
+    /// Creates a counter from a closure that returns the input tokens of the
+    /// given thread and tools.
+    #[must_use]
+    pub fn new<F>(counter: F) -> Self
+    where
+        F: Fn(&[Message], &tool::Set) -> u64 + Send + Sync + 'static,
+    {
+        Self(Arc::new(counter))
+    }
+
+    /// Returns the input tokens of the given thread and tools.
+    #[must_use]
+    pub fn count(&self, messages: &[Message], tools: &tool::Set) -> u64 {
+        (self.0)(messages, tools)
+    }
+}
+
+impl fmt::Debug for TokenCounter {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("TokenCounter")
+    }
+}
+
+impl PartialEq for TokenCounter {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TokenCounter {}
+
+impl Budget {
+    /// Returns an error if a model call with the given estimated usage would
+    /// exceed the budget of the session.
+    fn check(
+        &self,
+        session: &Session,
+        model: &Model,
+        pricing: &PricingTable,
+        estimate: &Usage,
+    ) -> Result<(), BudgetExceeded> {
+        if let Some(ref limit) = self.max_cost {
+            let estimated = pricing.cost(model, estimate);
+            if session.cost >= *limit || &session.cost + &estimated > *limit {
+                return Err(BudgetExceeded::Cost {
+                    spent: session.cost.clone(),
+                    estimated,
+                    limit: limit.clone(),
+                });
+            }
+        }
+        if let Some(limit) = self.max_tokens {
+            let used = session.usage.total_tokens().to_u64().unwrap_or(u64::MAX);
+            let estimated = estimate.total_tokens().to_u64().unwrap_or(u64::MAX);
+            if used >= limit || used.saturating_add(estimated) > limit {
+                return Err(BudgetExceeded::Tokens {
+                    used,
+                    estimated,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Supported AI models for message generation.
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
+#[expect(
+    clippy::large_enum_variant,
+    reason = "models are cloned into configs, not moved around in hot paths"
+)]
 pub enum Model {
     /// GPT-4.1 model (default).
     #[default]
@@ -75,50 +368,55 @@ pub enum Model {
     /// O4 Mini model.
     #[serde(rename = "o4-mini")]
     O4Mini,
+    /// Claude Opus 4 model.
+    #[serde(rename = "claude-opus-4-20250514")]
+    ClaudeOpus4,
+    /// Claude Sonnet 4 model.
+    #[serde(rename = "claude-sonnet-4-20250514")]
+    ClaudeSonnet4,
+    /// Claude 3.5 Haiku model.
+    #[serde(rename = "claude-3-5-haiku-20241022")]
+    Claude3_5Haiku,
+    /// A model with user-supplied prices, e.g. a fine-tuned `ft:` model.
+    Custom {
+        /// Name of the model as expected by the API.
+        name: String,
+        /// Prices of the model.
+        pricing: Pricing,
+    },
+    /// Any other model, identified by its name. Usage is not priced.
+    #[serde(untagged)]
+    Other(String),
 }
 
 impl Model {
-    fn cost_per_input_token(self) -> BigDecimal {
-        match self {
-            Self::Gpt4_1 => BigDecimal::from_f64(2.0),
-            Self::Gpt4_1Mini => BigDecimal::from_f64(0.4),
-            Self::Gpt4_1Nano => BigDecimal::from_f64(0.1),
-            Self::O3 => BigDecimal::from_f64(10.0),
-            Self::O4Mini => BigDecimal::from_f64(1.1),
-        }
-        .expect("failed to convert to bigdecimal")
-            / BigDecimal::from_f64(1_000_000.0).expect("failed to convert to bigdecimal")
-    }
-
-    fn cost_per_cached_input_token(self) -> BigDecimal {
-        match self {
-            Self::Gpt4_1 => BigDecimal::from_f64(0.5),
-            Self::Gpt4_1Mini => BigDecimal::from_f64(0.1),
-            Self::Gpt4_1Nano => BigDecimal::from_f64(0.025),
-            Self::O3 => BigDecimal::from_f64(2.5),
-            Self::O4Mini => BigDecimal::from_f64(0.275),
-        }
-        .expect("failed to convert to bigdecimal")
-            / BigDecimal::from_f64(1_000_000.0).expect("failed to convert to bigdecimal")
+    /// Returns the prices of the model from the built-in [`PricingTable`].
+    ///
+    /// Models without known prices are free.
+    #[must_use]
+    pub fn pricing(&self) -> Pricing {
+        PricingTable::builtin().pricing(self)
     }
 
-    fn cost_per_output_token(self) -> BigDecimal {
-        match self {
-            Self::Gpt4_1 => BigDecimal::from_f64(8.0),
-            Self::Gpt4_1Mini => BigDecimal::from_f64(1.6),
-            Self::Gpt4_1Nano => BigDecimal::from_f64(0.4),
-            Self::O3 => BigDecimal::from_f64(40.0),
-            Self::O4Mini => BigDecimal::from_f64(4.4),
-        }
-        .expect("failed to convert to bigdecimal")
-            / BigDecimal::from_f64(1_000_000.0).expect("failed to convert to bigdecimal")
+    /// Returns the cost of the given usage for this model using the built-in
+    /// [`PricingTable`].
+    #[must_use]
+    pub fn cost(&self, usage: &Usage) -> BigDecimal {
+        PricingTable::builtin().cost(self, usage)
     }
 
+    /// Returns whether the model reasons before it answers, i.e. accepts
+    /// [`config::Reasoning`]. `None` for custom and other models, which are
+    /// treated as reasoning models if reasoning is configured.
     #[must_use]
-    pub fn cost(self, usage: &Usage) -> BigDecimal {
-        self.cost_per_input_token() * usage.input_tokens.clone()
-            + self.cost_per_cached_input_token() * usage.cached_input_tokens.clone()
-            + self.cost_per_output_token() * usage.output_tokens.clone()
+    pub const fn reasons(&self) -> Option<bool> {
+        match *self {
+            Self::O3 | Self::O4Mini | Self::ClaudeOpus4 | Self::ClaudeSonnet4 => Some(true),
+            Self::Gpt4_1 | Self::Gpt4_1Mini | Self::Gpt4_1Nano | Self::Claude3_5Haiku => {
+                Some(false)
+            }
+            Self::Custom { .. } | Self::Other(_) => None,
+        }
     }
 }
 
@@ -130,12 +428,56 @@ impl Display for Model {
             Self::Gpt4_1Nano => write!(formatter, "gpt-4.1-nano"),
             Self::O3 => write!(formatter, "o3"),
             Self::O4Mini => write!(formatter, "o4-mini"),
+            Self::ClaudeOpus4 => write!(formatter, "claude-opus-4-20250514"),
+            Self::ClaudeSonnet4 => write!(formatter, "claude-sonnet-4-20250514"),
+            Self::Claude3_5Haiku => write!(formatter, "claude-3-5-haiku-20241022"),
+            Self::Custom { ref name, .. } | Self::Other(ref name) => formatter.write_str(name),
         }
     }
 }
 
 pub mod config {
+    use alloc::collections::BTreeMap;
+    use alloc::sync::Arc;
+    use core::fmt;
+    use core::time::Duration;
+
+    use bigdecimal::BigDecimal;
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
+    use serde_json::Value;
+
+    use crate::{
+        Error, Message, Model, Session, message,
+        util::{json_schema, make_strict},
+    };
+
+    /// Connection settings for the model API.
+    ///
+    /// Unset fields fall back to the provider's defaults, so the same
+    /// configuration can point at the official API, a local server such as
+    /// Ollama or vLLM, or an internal gateway.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct Client {
+        /// Base URL of the API, e.g. `http://localhost:11434/v1`.
+        pub base_url: Option<String>,
+        /// API key. Defaults to the provider's environment variable.
+        pub api_key: Option<String>,
+        /// Organization to bill requests to.
+        pub organization: Option<String>,
+        /// Project to bill requests to.
+        pub project: Option<String>,
+        /// Additional headers to send with every request.
+        pub headers: BTreeMap<String, String>,
+    }
+
+    impl Client {
+        /// Returns whether all settings fall back to the provider's defaults.
+        pub(crate) fn is_default(&self) -> bool {
+            *self == Self::default()
+        }
+    }
 
     /// Tool selection strategy for AI message generation.
     #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -148,6 +490,269 @@ pub mod config {
         /// Do not use tools.
         None,
     }
+
+    /// Settings for reasoning models such as [`Model::O3`] and [`Model::O4Mini`].
+    ///
+    /// For Claude models, they enable extended thinking. Models that do not
+    /// reason reject them.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Reasoning {
+        /// How much the model reasons before it answers. `None` uses the
+        /// model's default.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub effort: Option<ReasoningEffort>,
+        /// How the model summarizes its reasoning. The summaries are streamed
+        /// as [reasoning parts](message::ReasoningPart). `None` requests no
+        /// summaries.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub summary: Option<ReasoningSummary>,
+    }
+
+    /// How much a model reasons before it answers.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ReasoningEffort {
+        /// Answer quickly with few reasoning tokens.
+        Low,
+        /// Balance speed and reasoning.
+        Medium,
+        /// Reason thoroughly.
+        High,
+    }
+
+    /// Level of detail of a model's reasoning summaries.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ReasoningSummary {
+        /// The most detailed summary the model supports.
+        Auto,
+        /// A short summary.
+        Concise,
+        /// A detailed summary.
+        Detailed,
+    }
+
+    /// A JSON schema that the model's text must follow.
+    ///
+    /// The OpenAI APIs enforce the schema strictly. The Messages API has no
+    /// structured outputs, so the schema is added to the system prompt.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ResponseFormat {
+        /// Name of the schema. May only contain letters, digits, underscores
+        /// and dashes.
+        pub name: String,
+        /// The JSON schema of the output. Must describe an object.
+        pub schema: Value,
+    }
+
+    impl ResponseFormat {
+        /// Creates a response format from the schema of a type, generated like
+        /// the [parameters](crate::ToolBuilder::parameters) of tools.
+        ///
+        /// The schema is made strict: objects do not allow additional
+        /// properties and require all of their properties, so fields that may
+        /// be omitted must be nullable, e.g. `Option`s.
+        ///
+        /// # Errors
+        ///
+        /// Fails with [`Error::InvalidConfig`] if `T` is not an object, e.g. a `Vec`
+        /// or an enum. Wrap such types in a struct.
+        pub fn json_schema<T: JsonSchema>() -> Result<Self, Error> {
+            let mut schema = json_schema::<T>();
+            if schema.get("type") != Some(&Value::from("object")) {
+                return Err(Error::InvalidConfig(format!(
+                    "The response format must be an object, but {} is not",
+                    T::schema_name()
+                )));
+            }
+            make_strict(&mut schema);
+            Ok(Self {
+                name: T::schema_name()
+                    .chars()
+                    .map(|char| {
+                        if char.is_ascii_alphanumeric() || char == '-' {
+                            char
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect(),
+                schema: schema.to_value(),
+            })
+        }
+    }
+
+    /// Timeouts for a single model call. Unset timeouts never expire.
+    ///
+    /// A call that times out fails with [`Error::Timeout`], which is retried
+    /// like a network error if it happens before any output.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct Timeouts {
+        /// How long to wait for the first event of the response.
+        pub first_token: Option<Duration>,
+        /// How long to wait between two events of the response.
+        pub idle: Option<Duration>,
+        /// How long the entire response may take.
+        pub total: Option<Duration>,
+    }
+
+    /// The state of a generation after a step, passed to [`StopCondition`]s.
+    ///
+    /// A step is a single model call and the execution of the tools it called.
+    #[derive(Debug, Clone, Copy)]
+    #[non_exhaustive]
+    pub struct Step<'step> {
+        /// Number of completed steps, starting at `1`.
+        pub number: u32,
+        /// The assistant message generated so far.
+        pub message: &'step Message,
+        /// The session, including the cost so far.
+        pub session: &'step Session,
+    }
+
+    /// A condition that stops the generation after a step, before the next
+    /// model call.
+    ///
+    /// Conditions are not serialized. Two conditions are equal if they share
+    /// the same closure.
+    #[derive(Clone)]
+    pub struct StopCondition(Arc<dyn Fn(&Step<'_>) -> bool + Send + Sync>);
+
+    impl StopCondition {
+        /// Creates a condition from a closure that returns `true` to stop.
+        #[must_use]
+        pub fn new<F>(condition: F) -> Self
+        where
+            F: Fn(&Step<'_>) -> bool + Send + Sync + 'static,
+        {
+            Self(Arc::new(condition))
+        }
+
+        /// Stops once the tool with the given name was called.
+        #[must_use]
+        pub fn tool_called<S: Into<String>>(name: S) -> Self {
+            let name = name.into();
+            Self::new(move |step| {
+                step.message.parts.iter().any(|part| {
+                    matches!(
+                        *part,
+                        message::Part::Tool(message::ToolPart { ref tool }) if tool.name == name
+                    )
+                })
+            })
+        }
+
+        /// Stops once the cost of the session exceeds the given amount.
+        #[must_use]
+        pub fn cost_exceeds(cost: BigDecimal) -> Self {
+            Self::new(move |step| step.session.cost > cost)
+        }
+
+        /// Returns whether the generation should stop after the given step.
+        #[must_use]
+        pub fn is_met(&self, step: &Step<'_>) -> bool {
+            (self.0)(step)
+        }
+    }
+
+    impl fmt::Debug for StopCondition {
+        fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("StopCondition")
+        }
+    }
+
+    impl PartialEq for StopCondition {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    impl Eq for StopCondition {}
+
+    /// A model to fall back to when the previous models fail.
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct Fallback {
+        /// Model to use.
+        pub model: Model,
+        /// Connection settings for the model API. `None` uses the settings of
+        /// the primary model.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub client: Option<Client>,
+    }
+
+    impl From<Model> for Fallback {
+        fn from(model: Model) -> Self {
+            Self {
+                model,
+                client: None,
+            }
+        }
+    }
+
+    /// Retry policy for model calls that fail before producing any output.
+    ///
+    /// Only transient errors are retried (see [`Error::is_retryable`]). The
+    /// delay doubles after every attempt, starting at `initial_backoff` and
+    /// capped at `max_backoff`, unless the provider asked for a specific delay
+    /// with `Retry-After`. That delay is capped at `max_backoff` as well.
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct Retry {
+        /// Maximum number of attempts, including the first one. `1` disables
+        /// retries.
+        pub max_attempts: u32,
+        /// Delay before the first retry.
+        pub initial_backoff: Duration,
+        /// Upper bound for the delay between attempts, including delays that
+        /// providers ask for with `Retry-After`.
+        pub max_backoff: Duration,
+        /// Whether to randomize each delay between half and all of its value,
+        /// so that concurrent clients do not retry in lockstep.
+        pub jitter: bool,
+    }
+
+    impl Retry {
+        /// A policy that never retries.
+        #[must_use]
+        pub const fn never() -> Self {
+            Self {
+                max_attempts: 1,
+                initial_backoff: Duration::ZERO,
+                max_backoff: Duration::ZERO,
+                jitter: false,
+            }
+        }
+
+        /// Returns the delay before retrying after the given failed attempt,
+        /// starting at `1`.
+        #[must_use]
+        pub fn delay(&self, attempt: u32, error: &Error) -> Duration {
+            if let Some(retry_after) = error.retry_after() {
+                return retry_after.min(self.max_backoff);
+            }
+            let backoff = self
+                .initial_backoff
+                .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+                .min(self.max_backoff);
+            if !self.jitter {
+                return backoff;
+            }
+            let nanos = u64::try_from(backoff.as_nanos()).unwrap_or(u64::MAX);
+            Duration::from_nanos(fastrand::u64(nanos.saturating_div(2)..=nanos))
+        }
+    }
+
+    impl Default for Retry {
+        fn default() -> Self {
+            Self {
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(500),
+                max_backoff: Duration::from_secs(30),
+                jitter: true,
+            }
+        }
+    }
 }
 
 /// Configuration for generating AI messages.
@@ -157,6 +762,68 @@ pub struct GenerateConfig {
     pub model: Model,
     /// Tool selection strategy.
     pub tool_choice: config::ToolChoice,
+    /// Maximum number of tokens to generate per model call.
+    #[serde(default)]
+    pub max_output_tokens: Option<u32>,
+    /// Whether the model may call several tools at once. The calls are
+    /// executed concurrently unless a tool is [serial](Tool::is_serial).
+    #[serde(default)]
+    pub parallel_tool_calls: bool,
+    /// Connection settings for the model API.
+    #[serde(default, skip_serializing_if = "config::Client::is_default")]
+    pub client: config::Client,
+    /// Prices used for `Session` cost accounting. `None` uses the built-in
+    /// [`PricingTable`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingTable>,
+    /// Retry policy for model calls that fail before producing any output.
+    #[serde(default)]
+    pub retry: config::Retry,
+    /// Timeouts for model calls.
+    #[serde(default)]
+    pub timeouts: config::Timeouts,
+    /// Models to fall back to, in order, when the model fails before
+    /// producing any output, e.g. because it is rate limited or unavailable.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<config::Fallback>,
+    /// Maximum number of steps, i.e. model calls, of the generation. `None`
+    /// keeps calling the model as long as it calls tools.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    /// Conditions that stop the generation after a step. The generation stops
+    /// as soon as any of them is met.
+    #[serde(skip)]
+    pub stop_conditions: Vec<config::StopCondition>,
+    /// Whether the provider may store responses to continue the conversation
+    /// from the [`Session`]'s cursor. `None` uses the provider's default and
+    /// `Some(false)` always sends the whole thread.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<bool>,
+    /// Settings for reasoning models.
+    #[serde(default)]
+    pub reasoning: config::Reasoning,
+    /// Schema that the model's text must follow. See [`object::generate_object`]
+    /// for typed outputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<config::ResponseFormat>,
+}
+
+impl GenerateConfig {
+    /// Returns the configurations of the fallback models, in order.
+    fn fallback_configs(&self) -> Vec<Self> {
+        self.fallbacks
+            .iter()
+            .map(|fallback| Self {
+                model: fallback.model.clone(),
+                client: fallback
+                    .client
+                    .clone()
+                    .unwrap_or_else(|| self.client.clone()),
+                fallbacks: Vec::new(),
+                ..self.clone()
+            })
+            .collect()
+    }
 }
 
 /// Streams AI-generated messages based on the input messages and tools using the Responses API.
@@ -167,575 +834,2234 @@ pub struct GenerateConfig {
 /// * `messages` - Vector of input messages to process.
 /// * `tools` - Set of tools available for the AI to use.
 /// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
 ///
 /// # Returns
 ///
 /// A stream of `Result<Arc<Mutex<Message>>>` items representing the AI's responses.
+pub fn responses_stream(
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<OwnedMutexGuard<Message>, Error>> {
+    provider_stream(
+        openai::Responses::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+}
+
+/// Streams AI-generated messages based on the input messages and tools.
 ///
-/// # Panics
+/// # Arguments
 ///
-/// This function may panic if the `OpenAI` API key is invalid or if there are
-/// issues with the tool configuration.
-pub fn responses_stream(
+/// * `messages` - Vector of input messages to process.
+/// * `tools` - Set of tools available for the AI to use.
+/// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
+///
+/// # Returns
+///
+/// A stream of `Result<Message>` items representing the AI's responses.
+pub fn stream(
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<OwnedMutexGuard<Message>, Error>> {
+    provider_stream(
+        openai::ChatCompletions::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+}
+
+/// Streams AI-generated messages from the given provider, executing tools until
+/// the model stops calling them.
+///
+/// Every item is a snapshot of the entire assistant message. See
+/// [`provider_events`] for a stream of the individual changes.
+///
+/// # Arguments
+///
+/// * `provider` - The model backend to use.
+/// * `session` - Mutable reference to the session state.
+/// * `messages` - Vector of input messages to process.
+/// * `tools` - Set of tools available for the AI to use.
+/// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
+///
+/// # Returns
+///
+/// A stream of `Result<OwnedMutexGuard<Message>>` items representing the AI's responses.
+pub fn provider_stream<P: Provider>(
+    provider: P,
     session: &mut Session,
     messages: &[Message],
     tools: tool::Set,
     config: Option<GenerateConfig>,
-) -> impl Stream<Item = anyhow::Result<OwnedMutexGuard<Message>>> {
-    let config = config.unwrap_or_default();
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<OwnedMutexGuard<Message>, Error>> {
+    let events = provider_events(provider, session, messages, tools, config, cancellation);
+
+    Gen::new(|co| async move {
+        let mut events = pin!(events);
+        let assistant_message = Arc::new(Mutex::new(Message {
+            id: String::new(),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        }));
+        let mut accumulator = event::Accumulator::default();
 
-    let openai = openai_responses::Client::from_env().expect("failed to create openai client");
+        while let Some(result) = events.next().await {
+            let event = match result {
+                Ok(event) => event,
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+            if matches!(
+                event,
+                Event::StepStarted
+                    | Event::StepFinished
+                    | Event::Usage(_)
+                    | Event::Retry { .. }
+                    | Event::Finished { .. }
+            ) {
+                continue;
+            }
 
-    let thread = messages
-        .iter()
-        .cloned()
-        .map(Message::try_into)
-        .collect::<Result<Vec<Vec<_>>, _>>()
-        .expect("to convert messages")
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>();
+            let mut message = assistant_message.lock().await;
+            if let Err(error) = accumulator.apply(&mut message, &event) {
+                drop(message);
+                co.yield_(Err(error)).await;
+                return;
+            }
+            drop(message);
+
+            co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
+                .await;
+        }
+    })
+}
 
-    let tool_parameters = tools.values().map(Into::into).collect::<Vec<_>>();
+/// Streams the changes to the AI-generated message using the Responses API.
+///
+/// See [`provider_events`] for details.
+pub fn responses_events(
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<Event, Error>> {
+    provider_events(
+        openai::Responses::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+}
+
+/// Streams the changes to the AI-generated message using the Chat Completions API.
+///
+/// See [`provider_events`] for details.
+pub fn events(
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<Event, Error>> {
+    provider_events(
+        openai::ChatCompletions::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+}
+
+/// Streams the changes to the AI-generated message from the given provider,
+/// executing tools until the model stops calling them.
+///
+/// The stream starts with [`Event::MessageStarted`] and ends with
+/// [`Event::Finished`] unless an error occurs. The message can be
+/// reconstructed with an [`event::Accumulator`].
+///
+/// # Arguments
+///
+/// * `provider` - The model backend to use.
+/// * `session` - Mutable reference to the session state.
+/// * `messages` - Vector of input messages to process.
+/// * `tools` - Set of tools available for the AI to use.
+/// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
+///
+/// # Returns
+///
+/// A stream of `Result<Event>` items representing the changes to the AI's response.
+pub fn provider_events<P: Provider>(
+    provider: P,
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<Event, Error>> {
+    let mut config = config.unwrap_or_default();
+    let mut fallbacks = config.fallback_configs();
 
-    let model = config.model.to_string();
+    let thread = messages.to_vec();
 
     Gen::new(|co| async move {
-        let assistant_message = Arc::new(Mutex::new(Message {
+        let mut assistant_message = Message {
             id: Uuid::now_v7().to_string(),
             role: message::Role::Assistant,
             parts: Vec::new(),
-        }));
-        co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
-            .await;
-
+            model: Some(config.model.clone()),
+        };
+        let mut accumulator = event::Accumulator::default();
+        co.yield_(Ok(Event::MessageStarted {
+            id: assistant_message.id.clone(),
+            model: config.model.clone(),
+        }))
+        .await;
+
+        // Number of fallback models the previous turn went through. Once a
+        // model failed, the remaining turns are served by its fallback.
+        let mut fell_back = 0_usize;
+        let mut steps = 0_u32;
         loop {
-            let mut current_thread = thread.clone();
+            if let Some(fallback) = fallbacks
+                .drain(..fell_back.min(fallbacks.len()))
+                .next_back()
             {
-                let assistant_message = assistant_message.lock().await;
-                if !assistant_message.parts.is_empty() {
-                    current_thread.extend(
-                        Vec::try_from(assistant_message.clone()).expect("to convert message"),
-                    );
-                }
+                config = fallback;
             }
+            fell_back = 0;
+            let mut model = config.model.clone();
 
-            let request = Request::builder()
-                .model(model.clone())
-                .input(Input::List(current_thread.clone()))
-                .previous_response_id_optional(session.cursor.clone())
-                .tools(tool_parameters.clone())
-                .tool_choice(match config.tool_choice {
-                    config::ToolChoice::Auto => openai_responses::types::ToolChoice::Auto,
-                    config::ToolChoice::Required => openai_responses::types::ToolChoice::Required,
-                    config::ToolChoice::None => openai_responses::types::ToolChoice::None,
-                })
-                .parallel_tool_calls(false)
-                .build();
+            let mut current_thread = thread.clone();
+            if !assistant_message.parts.is_empty() {
+                current_thread.push(assistant_message.clone());
+            }
+
+            if let Some(ref budget) = session.budget {
+                let estimate = budget.token_counter.as_ref().map_or_else(
+                    || {
+                        if budget.preflight {
+                            estimate_input_usage(&current_thread, &tools)
+                        } else {
+                            Usage::default()
+                        }
+                    },
+                    |token_counter| Usage {
+                        input_tokens: token_counter.count(&current_thread, &tools).into(),
+                        ..Usage::default()
+                    },
+                );
+                let pricing = config
+                    .pricing
+                    .as_ref()
+                    .unwrap_or_else(|| PricingTable::builtin());
+                if let Err(error) = budget.check(session, &config.model, pricing, &estimate) {
+                    co.yield_(Err(error.into())).await;
+                    return;
+                }
+            }
 
-            let mut stream = openai.stream(request);
+            co.yield_(Ok(Event::StepStarted)).await;
+            let started_at = SystemTime::now();
+            let started = Instant::now();
+            let mut step = StepRecord {
+                model: model.clone(),
+                started_at,
+                latency: None,
+                duration: Duration::ZERO,
+                usage: Usage::default(),
+                cost: BigDecimal::from(0_u32),
+                tool_calls: 0,
+            };
 
-            let mut deltas = BTreeMap::new();
+            let cursor = session.cursor.clone();
+            // Index of the first part that this step writes to the message.
+            let part_index = assistant_message.parts.len();
+            let mut events = pin!(attempts(
+                &provider,
+                provider::Request {
+                    messages: &current_thread,
+                    tools: &tools,
+                    config: &config,
+                    cursor: cursor.as_ref(),
+                },
+                &fallbacks,
+            ));
+
+            let mut part_indices = BTreeMap::new();
             let mut tool_executions = JoinSet::new();
-
-            while let Some(result) = stream.next().await {
+            let mut tool_tasks = FxHashMap::default();
+            // Serial tools wait until all other tool calls completed.
+            let mut serial_tools = VecDeque::new();
+            let mut incomplete = None;
+            // A step that ends early is recorded too, so that the steps add up to the
+            // usage of the session.
+            let mut cancelled = false;
+            let mut failure = None;
+
+            loop {
+                let result = tokio::select! {
+                    biased;
+                    () = cancellation.cancelled() => None,
+                    result = events.next() => Some(result),
+                };
+                let Some(result) = result else {
+                    cancelled = true;
+                    break;
+                };
+                let Some(result) = result else {
+                    break;
+                };
                 let event = match result {
-                    Ok(response) => response,
-                    Err(error) => {
-                        if let StreamError::Stream(reqwest_eventsource::Error::InvalidStatusCode(
-                            _,
-                            response,
-                        )) = error
-                        {
-                            co.yield_(Err(anyhow::anyhow!(
-                                "Failed to create stream: {}",
-                                response.json::<Value>().await.expect("to get json")
-                            )))
-                            .await;
-                        } else {
-                            co.yield_(Err(anyhow::anyhow!("Failed to create stream: {error}")))
-                                .await;
+                    Ok(Attempt::Event(event)) => {
+                        step.latency.get_or_insert_with(|| started.elapsed());
+                        event
+                    }
+                    Ok(Attempt::Retry {
+                        attempt,
+                        delay,
+                        error,
+                    }) => {
+                        co.yield_(Ok(Event::Retry {
+                            attempt,
+                            delay,
+                            error: error.to_string(),
+                        }))
+                        .await;
+                        continue;
+                    }
+                    Ok(Attempt::Fallback {
+                        model: fallback,
+                        error,
+                    }) => {
+                        fell_back = fell_back.saturating_add(1);
+                        model = fallback.clone();
+                        let event = Event::Fallback {
+                            model: fallback,
+                            error: error.to_string(),
+                        };
+                        if let Err(error) = accumulator.apply(&mut assistant_message, &event) {
+                            failure = Some(error);
+                            break;
                         }
-                        return;
+                        co.yield_(Ok(event)).await;
+                        continue;
+                    }
+                    Err(error) => {
+                        failure = Some(error);
+                        break;
                     }
                 };
 
-                #[expect(clippy::wildcard_enum_match_arm, reason = "there are a lot of events")]
-                match event {
-                    openai_responses::types::Event::ResponseCompleted { response } => {
-                        if let Some(previous_response_id) = response.previous_response_id {
-                            session.cursor = Some(previous_response_id);
-                        }
-                        if let Some(responses_usage) = response.usage {
-                            session.cost += config.model.cost(&Usage {
-                                cached_input_tokens: responses_usage
-                                    .input_tokens_details
-                                    .cached_tokens
-                                    .into(),
-                                input_tokens: responses_usage
-                                    .input_tokens
-                                    .saturating_sub(
-                                        responses_usage.input_tokens_details.cached_tokens,
-                                    )
-                                    .into(),
-                                output_tokens: responses_usage.output_tokens.into(),
-                            });
-                        }
+                let event = match event {
+                    provider::Event::Usage(usage) => {
+                        // The cost is attributed to the model that served the turn.
+                        let cost = config
+                            .pricing
+                            .as_ref()
+                            .unwrap_or_else(|| PricingTable::builtin())
+                            .cost(&model, &usage);
+                        let model_usage = session.models.entry(model.to_string()).or_default();
+                        model_usage.usage.accumulate(&usage);
+                        model_usage.cost += &cost;
+                        session.usage.accumulate(&usage);
+                        session.cost += &cost;
+                        step.usage.accumulate(&usage);
+                        step.cost += cost;
+                        Event::Usage(usage)
                     }
-                    openai_responses::types::Event::OutputItemAdded {
-                        item: openai_responses::types::OutputItem::FunctionCall(function_call),
-                        output_index,
-                    } => {
-                        let mut assistant_message = assistant_message.lock().await;
-                        deltas.insert(
-                            (output_index, 0),
-                            (function_call.arguments, assistant_message.parts.len()),
-                        );
-                        assistant_message
-                            .parts
-                            .push(message::Part::Tool(message::ToolPart {
-                                tool: message::ToolCall {
-                                    id: function_call.call_id,
-                                    name: function_call.name,
-                                    args: Value::Null,
-                                    result: None,
-                                },
-                            }));
-                    }
-                    openai_responses::types::Event::ContentPartAdded {
-                        part:
-                            openai_responses::types::OutputContent::Text { text, .. }
-                            | openai_responses::types::OutputContent::Refusal { refusal: text, .. },
-                        content_index,
-                        output_index,
-                        ..
-                    } => {
-                        let mut assistant_message = assistant_message.lock().await;
-                        deltas.insert(
-                            (output_index, content_index),
-                            (text.clone(), assistant_message.parts.len()),
-                        );
-                        assistant_message
-                            .parts
-                            .push(message::Part::Text(message::TextPart { text }));
+                    provider::Event::Incomplete(reason) => {
+                        incomplete = Some(reason);
+                        continue;
                     }
-
-                    openai_responses::types::Event::RefusalDelta {
-                        delta,
-                        content_index,
-                        output_index,
-                        ..
+                    provider::Event::Cursor(response_id) => {
+                        session.cursor = Some(Cursor {
+                            response_id,
+                            message_id: assistant_message.id.clone(),
+                            part_index,
+                        });
+                        continue;
                     }
-                    | openai_responses::types::Event::OutputTextDelta {
+                    provider::Event::TextDelta { index, delta } => Event::TextDelta {
+                        part_index: *part_indices
+                            .entry(index)
+                            .or_insert(assistant_message.parts.len()),
                         delta,
-                        content_index,
-                        output_index,
-                        ..
+                    },
+                    provider::Event::ReasoningStarted {
+                        index,
+                        id,
+                        provider,
                     } => {
-                        let &mut (ref mut text, part_index) = deltas
-                            .get_mut(&(output_index, content_index))
-                            .expect("openai to correctly order events");
-                        text.push_str(&delta);
-
-                        let mut assistant_message = assistant_message.lock().await;
-                        let part = assistant_message
-                            .parts
-                            .get_mut(part_index)
-                            .expect("part to exist");
-                        let_assert!(
-                            &mut message::Part::Text(ref mut text_part) = part,
-                        );
-                        text.clone_into(&mut text_part.text);
-                        drop(assistant_message);
+                        let part_index = assistant_message.parts.len();
+                        part_indices.insert(index, part_index);
+                        Event::ReasoningStarted {
+                            part_index,
+                            id,
+                            provider: Some(provider),
+                        }
                     }
-                    openai_responses::types::Event::FunctionCallArgumentsDelta {
-                        delta,
-                        output_index,
-                        ..
+                    provider::Event::ReasoningDelta { index, delta } => {
+                        let Some(&part_index) = part_indices.get(&index) else {
+                            failure = Some(Error::UnexpectedEvent(format!(
+                                "Reasoning {index} before it started"
+                            )));
+                            break;
+                        };
+                        Event::ReasoningDelta { part_index, delta }
+                    }
+                    provider::Event::ReasoningDone {
+                        index,
+                        encrypted_content,
                     } => {
-                        let &mut (ref mut args, part_index) = deltas
-                            .get_mut(&(output_index, 0))
-                            .expect("openai to correctly order events");
-                        args.push_str(&delta);
-
-                        let mut assistant_message = assistant_message.lock().await;
-                        let part = assistant_message
-                            .parts
-                            .get_mut(part_index)
-                            .expect("part to exist");
-                        let_assert!(&mut message::Part::Tool(message::ToolPart { ref mut tool }) = part);
-                        tool.args = parse_incomplete_json(args).unwrap_or_default();
-
-                        let Some(tool_executor) = tools.get(&tool.name) else {
-                            continue;
+                        let Some(&part_index) = part_indices.get(&index) else {
+                            failure = Some(Error::UnexpectedEvent(format!(
+                                "Reasoning {index} finished before it started"
+                            )));
+                            break;
                         };
-                        if !tool_executor.is_streamable() {
-                            continue;
+                        Event::ReasoningDone {
+                            part_index,
+                            encrypted_content,
                         }
-
-                        if let Some(future) =
-                            tool_executor.execute(tool.id.clone(), tool.args.clone())
-                        {
-                            tool_executions.spawn(future.map(move |result| (part_index, result)));
+                    }
+                    provider::Event::ToolCallStarted { index, id, name } => {
+                        let part_index = assistant_message.parts.len();
+                        part_indices.insert(index, part_index);
+                        let tool_calls = session.tool_calls.entry(name.clone()).or_default();
+                        *tool_calls = tool_calls.saturating_add(1);
+                        step.tool_calls = step.tool_calls.saturating_add(1);
+                        Event::ToolCallStarted {
+                            part_index,
+                            id,
+                            name,
                         }
-                        drop(assistant_message);
-                        continue;
                     }
-
+                    provider::Event::ToolArgsDelta { index, delta } => {
+                        let Some(&part_index) = part_indices.get(&index) else {
+                            failure = Some(Error::UnexpectedEvent(format!(
+                                "Arguments for tool call {index} before it started"
+                            )));
+                            break;
+                        };
+                        Event::ToolArgsDelta { part_index, delta }
+                    }
                     // As soon as the function call arguments are done, we can execute tools, if they are available.
-                    openai_responses::types::Event::FunctionCallArgumentsDone {
-                        output_index,
-                        ..
-                    } => {
-                        let &mut (_, part_index) = deltas
-                            .get_mut(&(output_index, 0))
-                            .expect("openai to correctly order events");
-
-                        let mut assistant_message = assistant_message.lock().await;
-                        let part = assistant_message
-                            .parts
-                            .get_mut(part_index)
-                            .expect("part to exist");
-                        let_assert!(
-                            &mut message::Part::Tool(message::ToolPart {
-                                ref mut tool
-                            }) = part
-                        );
-
+                    provider::Event::ToolCallDone { index } => {
+                        let Some(&part_index) = part_indices.get(&index) else {
+                            failure = Some(Error::UnexpectedEvent(format!(
+                                "Tool call {index} finished before it started"
+                            )));
+                            break;
+                        };
+                        let tool = match tool_call(&assistant_message, part_index) {
+                            Ok(tool) => tool,
+                            Err(error) => {
+                                failure = Some(error);
+                                break;
+                            }
+                        };
                         let Some(tool_executor) = tools.get(&tool.name) else {
-                            tool.result = Some(json!(format!("No such tool: {}", tool.name)));
+                            let result = json!(format!("No such tool: {}", tool.name)).into();
+                            let event = Event::ToolResult { part_index, result };
+                            if let Err(error) = accumulator.apply(&mut assistant_message, &event) {
+                                failure = Some(error);
+                                break;
+                            }
+                            co.yield_(Ok(event)).await;
                             continue;
                         };
-                        if tool_executor.is_streamable() {
-                            continue;
-                        }
-
-                        if let Some(future) =
-                            tool_executor.execute(tool.id.clone(), tool.args.clone())
+                        if !tool_executor.is_streamable()
+                            && let Some(future) = tool_executor.execute(
+                                tool.id.clone(),
+                                tool.args.clone(),
+                                cancellation.clone(),
+                            )
                         {
-                            tool_executions.spawn(future.map(move |result| (part_index, result)));
+                            if tool_executor.is_serial() {
+                                serial_tools.push_back((part_index, future));
+                            } else {
+                                let task = tool_executions.spawn(future);
+                                tool_tasks.insert(task.id(), part_index);
+                            }
                         }
-                        drop(assistant_message);
-                        continue;
-                    }
-                    _ => {
-                        // Ignore other events
                         continue;
                     }
+                };
+
+                if let Err(error) = accumulator.apply(&mut assistant_message, &event) {
+                    failure = Some(error);
+                    break;
                 }
 
-                co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
-                    .await;
+                // Streamable tools are executed for every chunk of arguments.
+                if let Event::ToolArgsDelta { part_index, .. } = event
+                    && let Ok(tool) = tool_call(&assistant_message, part_index)
+                    && let Some(tool_executor) = tools.get(&tool.name)
+                    && tool_executor.is_streamable()
+                    && let Some(future) = tool_executor.execute(
+                        tool.id.clone(),
+                        tool.args.clone(),
+                        cancellation.clone(),
+                    )
+                {
+                    let task = tool_executions.spawn(future);
+                    tool_tasks.insert(task.id(), part_index);
+                }
+
+                co.yield_(Ok(event)).await;
             }
 
-            if tool_executions.is_empty() {
+            step.model = model;
+            step.duration = started.elapsed();
+            let model_usage = session.models.entry(step.model.to_string()).or_default();
+            model_usage.calls = model_usage.calls.saturating_add(1);
+            session.steps.push(step);
+
+            if cancelled {
+                tool_executions.abort_all();
+                stop(
+                    &co,
+                    &mut assistant_message,
+                    &mut accumulator,
+                    FinishReason::Cancelled,
+                )
+                .await;
                 return;
             }
-
-            while let Some((part_index, result)) = tool_executions
-                .join_next()
-                .await
-                .transpose()
-                .expect("tool to be executed")
-            {
-                let result = match result {
-                    Ok(result) => Some(result),
-                    Err(error) => Some(json!(format!("Error: {error}"))),
-                };
-                let mut assistant_message = assistant_message.lock().await;
-                let part = assistant_message
-                    .parts
-                    .get_mut(part_index)
-                    .expect("part to exist");
-                let_assert!(
-                    &mut message::Part::Tool(message::ToolPart {
-                        ref mut tool,
-                    }) = part,
-                );
-                tool.result = result;
-                drop(assistant_message);
+            if let Some(error) = failure {
+                co.yield_(Err(error)).await;
+                return;
+            }
+            if let Some(reason) = incomplete {
+                tool_executions.abort_all();
+                stop(&co, &mut assistant_message, &mut accumulator, reason).await;
+                return;
             }
 
-            co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
+            if tool_executions.is_empty() && serial_tools.is_empty() {
+                co.yield_(Ok(Event::StepFinished)).await;
+                co.yield_(Ok(Event::Finished {
+                    reason: FinishReason::Stop,
+                }))
                 .await;
+                return;
+            }
+
+            loop {
+                let joined = tokio::select! {
+                    biased;
+                    () = cancellation.cancelled() => None,
+                    joined = tool_executions.join_next_with_id() => Some(joined),
+                };
+                let Some(joined) = joined else {
+                    tool_executions.abort_all();
+                    stop(
+                        &co,
+                        &mut assistant_message,
+                        &mut accumulator,
+                        FinishReason::Cancelled,
+                    )
+                    .await;
+                    return;
+                };
+                let Some(joined) = joined else {
+                    let Some((part_index, future)) = serial_tools.pop_front() else {
+                        break;
+                    };
+                    let task = tool_executions.spawn(future);
+                    tool_tasks.insert(task.id(), part_index);
+                    continue;
+                };
+                let (task_id, result) = match joined {
+                    Ok((task_id, Ok(result))) => (task_id, result),
+                    // Timeouts are structured so the model can tell them apart from failures.
+                    Ok((task_id, Err(error))) => (
+                        task_id,
+                        error
+                            .downcast_ref::<tool::TimedOut>()
+                            .map_or_else(
+                                || json!(format!("Error: {error}")),
+                                |timed_out| {
+                                    json!({ "error": "timeout", "message": timed_out.to_string() })
+                                },
+                            )
+                            .into(),
+                    ),
+                    // A panicking tool must not take down the loop.
+                    Err(error) => (error.id(), json!(format!("Error: {error}")).into()),
+                };
+                let Some(&part_index) = tool_tasks.get(&task_id) else {
+                    continue;
+                };
+                let event = Event::ToolResult { part_index, result };
+                if let Err(error) = accumulator.apply(&mut assistant_message, &event) {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+                co.yield_(Ok(event)).await;
+            }
+            co.yield_(Ok(Event::StepFinished)).await;
 
             if assistant_message
-                .lock()
-                .await
                 .tool_calls()
-                .filter(|tool_call| tool_call.result.is_none())
-                .count()
-                > 0
+                .any(|tool_call| tool_call.result.is_none())
             {
                 // There are some client tool calls that need to be executed.
+                co.yield_(Ok(Event::Finished {
+                    reason: FinishReason::ToolCalls,
+                }))
+                .await;
+                return;
+            }
+
+            steps = steps.saturating_add(1);
+            let step = config::Step {
+                number: steps,
+                message: &assistant_message,
+                session,
+            };
+            let reason = if config.max_steps.is_some_and(|max_steps| steps >= max_steps) {
+                Some(FinishReason::MaxSteps)
+            } else if config
+                .stop_conditions
+                .iter()
+                .any(|condition| condition.is_met(&step))
+            {
+                Some(FinishReason::StopCondition)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                co.yield_(Ok(Event::Finished { reason })).await;
                 return;
             }
         }
     })
 }
 
-/// Streams AI-generated messages based on the input messages and tools.
+/// Generates a message using the Responses API and waits until it is complete.
 ///
-/// # Arguments
+/// See [`provider_generate`] for details.
 ///
-/// * `messages` - Vector of input messages to process
-/// * `tools` - Set of tools available for the AI to use
-/// * `config` - Optional configuration for message generation
+/// # Errors
 ///
-/// # Returns
+/// Returns the first error of the generation.
+pub async fn responses_generate(
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> Result<Generation, Error> {
+    provider_generate(
+        openai::Responses::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+    .await
+}
+
+/// Generates a message using the Chat Completions API and waits until it is
+/// complete.
 ///
-/// A stream of `Result<Message>` items representing the AI's responses
+/// See [`provider_generate`] for details.
 ///
-/// # Panics
+/// # Errors
 ///
-/// This function may panic if the `OpenAI` API key is invalid or if there are
-/// issues with the tool configuration.
-pub fn stream(
+/// Returns the first error of the generation.
+pub async fn generate(
     session: &mut Session,
     messages: &[Message],
     tools: tool::Set,
     config: Option<GenerateConfig>,
-) -> impl Stream<Item = anyhow::Result<OwnedMutexGuard<Message>>> {
-    let config = config.unwrap_or_default();
+    cancellation: CancellationToken,
+) -> Result<Generation, Error> {
+    provider_generate(
+        openai::ChatCompletions::new(),
+        session,
+        messages,
+        tools,
+        config,
+        cancellation,
+    )
+    .await
+}
 
-    let openai = async_openai::Client::new();
+/// Generates a message from the given provider, executing tools until the
+/// model stops calling them, and waits until it is complete.
+///
+/// This runs the same loop as [`provider_events`] without streaming it. The
+/// session is updated like it is for streams.
+///
+/// # Arguments
+///
+/// * `provider` - The model backend to use.
+/// * `session` - Mutable reference to the session state.
+/// * `messages` - Vector of input messages to process.
+/// * `tools` - Set of tools available for the AI to use.
+/// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
+///
+/// # Errors
+///
+/// Returns the first error of the generation.
+pub async fn provider_generate<P: Provider>(
+    provider: P,
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> Result<Generation, Error> {
+    let first_step = session.steps.len();
+    let mut message = Message {
+        id: String::new(),
+        role: message::Role::Assistant,
+        parts: Vec::new(),
+        model: None,
+    };
+    let mut accumulator = event::Accumulator::default();
+
+    // The events borrow the session until they are dropped.
+    let finish_reason = {
+        let mut events = pin!(provider_events(
+            provider,
+            session,
+            messages,
+            tools,
+            config,
+            cancellation,
+        ));
+        let mut finish_reason = None;
+        while let Some(event) = events.next().await {
+            let event = event?;
+            accumulator.apply(&mut message, &event)?;
+            if let Event::Finished { reason } = event {
+                finish_reason = Some(reason);
+            }
+        }
+        finish_reason
+    };
+    let Some(finish_reason) = finish_reason else {
+        return Err(Error::UnexpectedEvent(String::from(
+            "The generation ended before it finished",
+        )));
+    };
+
+    let steps = session.steps.get(first_step..).unwrap_or_default().to_vec();
+    let mut usage = Usage::default();
+    let mut cost = BigDecimal::from(0_u32);
+    for step in &steps {
+        usage.accumulate(&step.usage);
+        cost += &step.cost;
+    }
+    Ok(Generation {
+        message,
+        steps,
+        usage,
+        cost,
+        finish_reason,
+    })
+}
 
-    let thread = messages
-        .iter()
-        .cloned()
-        .map(Message::try_into)
-        .collect::<Result<Vec<Vec<_>>, _>>()
-        .expect("to convert messages")
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>();
+/// Roughly estimates the input usage of a model call from the size of its text.
+fn estimate_input_usage(messages: &[Message], tools: &tool::Set) -> Usage {
+    /// Bytes of text per token.
+    const BYTES_PER_TOKEN: u64 = 4;
+
+    let tools: Vec<Value> = tools
+        .values()
+        .map(|tool| {
+            json!({
+                "name": tool.name(),
+                "description": tool.description(),
+                "parameters": tool.parameters(),
+            })
+        })
+        .collect();
+    let bytes = serde_json::to_value((messages, tools)).map_or(0, |value| text_bytes(&value));
+    Usage {
+        input_tokens: BigDecimal::from(
+            u64::try_from(bytes)
+                .unwrap_or(u64::MAX)
+                .div_ceil(BYTES_PER_TOKEN),
+        ),
+        ..Usage::default()
+    }
+}
 
-    let tool_parameters = tools.values().map(Into::into).collect::<Vec<_>>();
+/// Returns the bytes of the strings in a JSON value.
+///
+/// Base64 content is skipped: providers bill images and files by their
+/// dimensions or pages, which are not known here.
+fn text_bytes(value: &Value) -> usize {
+    match *value {
+        Value::String(ref string) => string.len(),
+        Value::Array(ref values) => values.iter().map(text_bytes).sum(),
+        Value::Object(ref object) => {
+            let base64 = object.get("type").and_then(Value::as_str) == Some("base64");
+            object
+                .iter()
+                .filter(|&(key, _)| !(base64 && key == "data"))
+                .map(|(_, value)| text_bytes(value))
+                .sum()
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+    }
+}
 
-    let model = config.model.to_string();
+/// Finishes a generation that was cancelled or whose response was incomplete.
+///
+/// Tool calls without a result are resolved with an error, so that the message
+/// can be sent to the model again, and the current step is finished.
+async fn stop(
+    co: &Co<Result<Event, Error>>,
+    message: &mut Message,
+    accumulator: &mut event::Accumulator,
+    reason: FinishReason,
+) {
+    let result = message::ToolOutput::from(if reason == FinishReason::Cancelled {
+        json!("Error: Cancelled")
+    } else {
+        json!("Error: The response was incomplete")
+    });
+    let pending: Vec<usize> = message
+        .parts
+        .iter()
+        .enumerate()
+        .filter(|&(_, part)| {
+            matches!(
+                *part,
+                message::Part::Tool(message::ToolPart { ref tool }) if tool.result.is_none()
+            )
+        })
+        .map(|(part_index, _)| part_index)
+        .collect();
+    for part_index in pending {
+        let event = Event::ToolResult {
+            part_index,
+            result: result.clone(),
+        };
+        if let Err(error) = accumulator.apply(message, &event) {
+            co.yield_(Err(error)).await;
+            return;
+        }
+        co.yield_(Ok(event)).await;
+    }
+    co.yield_(Ok(Event::StepFinished)).await;
+    co.yield_(Ok(Event::Finished { reason })).await;
+}
 
-    Gen::new(|co| async move {
-        let assistant_message = Arc::new(Mutex::new(Message {
-            id: Uuid::now_v7().to_string(),
-            role: message::Role::Assistant,
-            parts: Vec::new(),
-        }));
-        co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
-            .await;
+/// An item of a model turn that is retried according to [`config::Retry`].
+enum Attempt {
+    /// An event of the current attempt.
+    Event(provider::Event),
+    /// The attempt failed and the turn is retried after `delay`.
+    Retry {
+        attempt: u32,
+        delay: Duration,
+        error: Error,
+    },
+    /// The model failed and the turn is retried on the next fallback model.
+    Fallback { model: Model, error: Error },
+}
 
+/// Streams a single model turn, retrying transient errors that occur before
+/// the provider produced any event and then falling back to the next model.
+///
+/// Once an event has been received, errors are returned as is, since the
+/// caller may already have acted on the partial output.
+fn attempts<'request, P: Provider>(
+    provider: &'request P,
+    mut request: provider::Request<'request>,
+    fallbacks: &'request [GenerateConfig],
+) -> impl Stream<Item = Result<Attempt, Error>> + 'request {
+    Gen::new(move |co| async move {
+        let mut fallbacks = fallbacks.iter();
+        let mut attempt = 1_u32;
         loop {
-            let mut current_thread = thread.clone();
-            {
-                let assistant_message = assistant_message.lock().await;
-                if !assistant_message.parts.is_empty() {
-                    current_thread.extend(
-                        Vec::try_from(assistant_message.clone()).expect("to convert message"),
-                    );
+            let timeouts = &request.config.timeouts;
+            let total = timeouts
+                .total
+                .and_then(|total| Instant::now().checked_add(total));
+            let mut events = provider.stream(request);
+            let mut started = false;
+            let mut failure = None;
+            loop {
+                let wait = if started {
+                    timeouts.idle
+                } else {
+                    timeouts.first_token
+                };
+                let deadline = wait
+                    .and_then(|wait| Instant::now().checked_add(wait))
+                    .into_iter()
+                    .chain(total)
+                    .min();
+                let result = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, events.next())
+                        .await
+                        .unwrap_or_else(|_elapsed| {
+                            Some(Err(Error::Timeout(String::from(
+                                if total.is_some_and(|total| total <= Instant::now()) {
+                                    "the response"
+                                } else if started {
+                                    "the next chunk"
+                                } else {
+                                    "the first token"
+                                },
+                            ))))
+                        }),
+                    None => events.next().await,
+                };
+                let Some(result) = result else {
+                    break;
+                };
+                match result {
+                    Ok(event) => {
+                        started = true;
+                        co.yield_(Ok(Attempt::Event(event))).await;
+                    }
+                    Err(error) if !started => {
+                        failure = Some(error);
+                        break;
+                    }
+                    Err(error) => {
+                        co.yield_(Err(error)).await;
+                        return;
+                    }
                 }
             }
+            drop(events);
+            let Some(error) = failure else {
+                return;
+            };
+
+            let retry = &request.config.retry;
+            if attempt < retry.max_attempts && error.is_retryable() {
+                let delay = retry.delay(attempt, &error);
+                co.yield_(Ok(Attempt::Retry {
+                    attempt,
+                    delay,
+                    error,
+                }))
+                .await;
+                tokio::time::sleep(delay).await;
+                attempt = attempt.saturating_add(1);
+            } else if error.allows_fallback()
+                && let Some(config) = fallbacks.next()
+            {
+                co.yield_(Ok(Attempt::Fallback {
+                    model: config.model.clone(),
+                    error,
+                }))
+                .await;
+                request.config = config;
+                attempt = 1;
+            } else {
+                co.yield_(Err(error)).await;
+                return;
+            }
+        }
+    })
+}
+
+/// Returns the tool call at the given part index of the message.
+fn tool_call(message: &Message, part_index: usize) -> Result<&message::ToolCall, Error> {
+    let Some(&message::Part::Tool(message::ToolPart { ref tool })) = message.parts.get(part_index)
+    else {
+        return Err(Error::UnexpectedEvent(format!(
+            "No tool part at index {part_index}"
+        )));
+    };
+    Ok(tool)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures::{TryStreamExt as _, stream};
+    use tool::SetExt as _;
+
+    use super::*;
+
+    /// A provider that replays one list of events per turn.
+    struct Scripted {
+        turns: Vec<Vec<Result<provider::Event, Error>>>,
+        calls: AtomicUsize,
+    }
+
+    impl Scripted {
+        fn new(turns: Vec<Vec<provider::Event>>) -> Self {
+            Self::with_results(
+                turns
+                    .into_iter()
+                    .map(|events| events.into_iter().map(Ok).collect())
+                    .collect(),
+            )
+        }
+
+        const fn with_results(turns: Vec<Vec<Result<provider::Event, Error>>>) -> Self {
+            Self {
+                turns,
+                calls: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Provider for Scripted {
+        fn stream<'request>(
+            &'request self,
+            _request: provider::Request<'request>,
+        ) -> futures::stream::BoxStream<'request, Result<provider::Event, Error>> {
+            let turn = self.calls.fetch_add(1, Ordering::SeqCst);
+            let events = self.turns.get(turn).cloned().unwrap_or_default();
+            stream::iter(events).boxed()
+        }
+    }
+
+    fn add_tool() -> tool::Set {
+        #[derive(serde::Deserialize, schemars::JsonSchema)]
+        struct Numbers {
+            left: i64,
+            right: i64,
+        }
+
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("add")
+                .parameters::<Numbers>()
+                .executor(
+                    async |tool::extract::Args(numbers): tool::extract::Args<Numbers>| {
+                        anyhow::Ok(numbers.left.saturating_add(numbers.right))
+                    },
+                )
+                .build()
+                .expect("to build tool"),
+        );
+        tools
+    }
+
+    #[tokio::test]
+    async fn streams_events() {
+        let provider = Scripted::new(vec![
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("Adding."),
+                },
+                provider::Event::ToolCallStarted {
+                    index: 1,
+                    id: String::from("call"),
+                    name: String::from("add"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 1,
+                    delta: String::from(r#"{"left": 1, "right": 2}"#),
+                },
+                provider::Event::ToolCallDone { index: 1 },
+            ],
+            vec![provider::Event::TextDelta {
+                index: 0,
+                delta: String::from("It is 3."),
+            }],
+        ]);
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            add_tool(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+
+        let mut message = Message {
+            id: String::new(),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        };
+        let mut accumulator = event::Accumulator::default();
+        for event in &events {
+            accumulator
+                .apply(&mut message, event)
+                .expect("event to apply");
+        }
 
-            let request = CreateChatCompletionRequestArgs::default()
-                .model(model.clone())
-                .messages(current_thread.clone())
-                .tools(tool_parameters.clone())
-                .parallel_tool_calls(false)
-                .tool_choice(match config.tool_choice {
-                    config::ToolChoice::Auto => ChatCompletionToolChoiceOption::Auto,
-                    config::ToolChoice::Required => ChatCompletionToolChoiceOption::Required,
-                    config::ToolChoice::None => ChatCompletionToolChoiceOption::None,
+        assert_eq!(
+            events.last(),
+            Some(&Event::Finished {
+                reason: FinishReason::Stop
+            })
+        );
+        assert_eq!(
+            message.parts,
+            vec![
+                message::Part::Text(message::TextPart {
+                    text: String::from("Adding."),
+                }),
+                message::Part::Tool(message::ToolPart {
+                    tool: message::ToolCall {
+                        id: String::from("call"),
+                        name: String::from("add"),
+                        args: json!({ "left": 1_i32, "right": 2_i32 }),
+                        result: Some(json!(3_i32).into()),
+                    },
+                }),
+                message::Part::Text(message::TextPart {
+                    text: String::from("It is 3."),
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn survives_panicking_tools() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::ToolCallStarted {
+                index: 0,
+                id: String::from("call"),
+                name: String::from("explode"),
+            },
+            provider::Event::ToolCallDone { index: 0 },
+        ]]);
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("explode")
+                .executor(async || -> anyhow::Result<()> { panic!("boom") })
+                .build()
+                .expect("to build tool"),
+        );
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+        assert!(
+            events.iter().any(|event| matches!(
+                *event,
+                Event::ToolResult {
+                    part_index: 0,
+                    result: message::ToolOutput::Json { ref value },
+                } if value.as_str().is_some_and(|value| value.starts_with("Error:"))
+            )),
+            "{events:#?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn stores_tool_outputs() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::ToolCallStarted {
+                index: 0,
+                id: String::from("call"),
+                name: String::from("screenshot"),
+            },
+            provider::Event::ToolCallDone { index: 0 },
+        ]]);
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("screenshot")
+                .output_executor(async || anyhow::Ok(message::ToolOutput::text("blank")))
+                .build()
+                .expect("to build tool"),
+        );
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+        assert!(
+            events.contains(&Event::ToolResult {
+                part_index: 0,
+                result: message::ToolOutput::text("blank"),
+            }),
+            "{events:#?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cancels_pending_tools() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::ToolCallStarted {
+                index: 0,
+                id: String::from("call"),
+                name: String::from("stop"),
+            },
+            provider::Event::ToolCallDone { index: 0 },
+        ]]);
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("stop")
+                .executor(
+                    async |tool::extract::Cancellation(cancellation): tool::extract::Cancellation| {
+                        cancellation.cancel();
+                        core::future::pending::<anyhow::Result<()>>().await
+                    },
+                )
+                .build()
+                .expect("to build tool"),
+        );
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+
+        assert_eq!(
+            events.get(events.len().saturating_sub(3)..),
+            Some(
+                [
+                    Event::ToolResult {
+                        part_index: 0,
+                        result: json!("Error: Cancelled").into(),
+                    },
+                    Event::StepFinished,
+                    Event::Finished {
+                        reason: FinishReason::Cancelled,
+                    },
+                ]
+                .as_slice()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn times_out_tools() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::ToolCallStarted {
+                index: 0,
+                id: String::from("call"),
+                name: String::from("hang"),
+            },
+            provider::Event::ToolCallDone { index: 0 },
+        ]]);
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("hang")
+                .timeout(Duration::from_millis(1))
+                .executor(async || core::future::pending::<anyhow::Result<()>>().await)
+                .build()
+                .expect("to build tool"),
+        );
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+        assert!(
+            events.contains(&Event::ToolResult {
+                part_index: 0,
+                result: json!({ "error": "timeout", "message": "Tool timed out after 1ms" }).into(),
+            }),
+            "{events:#?}"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_serial_tools_from_their_start() {
+        let call = |index: usize, name: &str| {
+            [
+                provider::Event::ToolCallStarted {
+                    index,
+                    id: format!("call_{index}"),
+                    name: name.to_owned(),
+                },
+                provider::Event::ToolCallDone { index },
+            ]
+        };
+        let provider = Scripted::new(vec![[call(0, "slow"), call(1, "serial")].concat()]);
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("slow")
+                .executor(async || {
+                    tokio::time::sleep(Duration::from_secs(6)).await;
+                    anyhow::Ok("slow")
                 })
-                .stream_options(ChatCompletionStreamOptions {
-                    include_usage: true,
+                .build()
+                .expect("to build tool"),
+        );
+        tools.add(
+            ToolBuilder::default()
+                .name("serial")
+                .serial(true)
+                .timeout(Duration::from_secs(5))
+                .executor(async || {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    anyhow::Ok("serial")
                 })
                 .build()
-                .expect("failed to build request");
+                .expect("to build tool"),
+        );
+        let config = GenerateConfig {
+            parallel_tool_calls: true,
+            ..GenerateConfig::default()
+        };
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            Some(config),
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+        assert!(
+            events.contains(&Event::ToolResult {
+                part_index: 1,
+                result: json!("serial").into(),
+            }),
+            "{events:#?}"
+        );
+    }
 
-            let mut stream = match openai.chat().create_stream(request.clone()).await {
-                Ok(stream) => stream,
-                Err(error) => {
-                    co.yield_(Err(anyhow::anyhow!("Failed to create stream: {error}")))
-                        .await;
-                    return;
-                }
-            };
+    #[tokio::test]
+    async fn times_out_model_calls() {
+        /// A provider that never responds.
+        struct Hanging;
+
+        impl Provider for Hanging {
+            fn stream<'request>(
+                &'request self,
+                _request: provider::Request<'request>,
+            ) -> futures::stream::BoxStream<'request, Result<provider::Event, Error>> {
+                stream::pending().boxed()
+            }
+        }
 
-            let mut tool_deltas: BTreeMap<u32, (String, usize)> = BTreeMap::new();
-            let mut tool_executions = JoinSet::new();
+        let config = GenerateConfig {
+            retry: config::Retry::never(),
+            timeouts: config::Timeouts {
+                first_token: Some(Duration::from_millis(10)),
+                ..config::Timeouts::default()
+            },
+            ..GenerateConfig::default()
+        };
+        let mut session = Session::default();
+        let result: Result<Vec<Event>, Error> = provider_events(
+            Hanging,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            Some(config),
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(
+            matches!(result, Err(Error::Timeout(ref phase)) if phase == "the first token"),
+            "{result:#?}"
+        );
+    }
 
-            while let Some(result) = stream.next().await {
-                let response = match result {
-                    Ok(response) => response,
-                    Err(error) => {
-                        co.yield_(Err(anyhow::anyhow!("Failed to create stream: {error}")))
-                            .await;
-                        return;
-                    }
-                };
+    #[tokio::test]
+    async fn runs_serial_tools_after_parallel_ones() {
+        struct State {
+            barrier: tokio::sync::Barrier,
+            done: AtomicUsize,
+        }
 
-                let is_last_chunk = response.choices.is_empty();
-                if let Some(chat_choice) = response.choices.into_iter().next() {
-                    // Accumulate text
-                    if let Some(content) = chat_choice.delta.content {
-                        if let Some(&mut message::Part::Text(ref mut text_part)) =
-                            assistant_message.lock().await.parts.last_mut()
-                        {
-                            text_part.text.push_str(&content);
-                        } else {
-                            assistant_message
-                                .lock()
-                                .await
-                                .parts
-                                .push(message::Part::Text(message::TextPart { text: content }));
-                        }
-                    }
+        let call = |index: usize, name: &str| {
+            [
+                provider::Event::ToolCallStarted {
+                    index,
+                    id: format!("call_{index}"),
+                    name: name.to_owned(),
+                },
+                provider::Event::ToolCallDone { index },
+            ]
+        };
+        let provider = Scripted::new(vec![
+            [call(0, "count"), call(1, "wait"), call(2, "wait")].concat(),
+        ]);
+        let state = Arc::new(State {
+            barrier: tokio::sync::Barrier::new(2),
+            done: AtomicUsize::new(0),
+        });
+        let mut tools = tool::Set::default();
+        tools.add(
+            ToolBuilder::default()
+                .name("wait")
+                .context(Arc::clone(&state))
+                .executor(
+                    async |tool::extract::Context(state): tool::extract::Context<Arc<State>>| {
+                        // Only completes if both calls run concurrently.
+                        state.barrier.wait().await;
+                        anyhow::Ok(state.done.fetch_add(1, Ordering::SeqCst))
+                    },
+                )
+                .build()
+                .expect("to build tool"),
+        );
+        tools.add(
+            ToolBuilder::default()
+                .name("count")
+                .serial(true)
+                .context(state)
+                .executor(
+                    async |tool::extract::Context(state): tool::extract::Context<Arc<State>>| {
+                        anyhow::Ok(state.done.load(Ordering::SeqCst))
+                    },
+                )
+                .build()
+                .expect("to build tool"),
+        );
+        let config = GenerateConfig {
+            parallel_tool_calls: true,
+            ..GenerateConfig::default()
+        };
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tools,
+            Some(config),
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+
+        let results: Vec<(usize, &message::ToolOutput)> = events
+            .iter()
+            .filter_map(|event| match *event {
+                Event::ToolResult {
+                    part_index,
+                    ref result,
+                } => Some((part_index, result)),
+                Event::MessageStarted { .. }
+                | Event::TextDelta { .. }
+                | Event::ToolCallStarted { .. }
+                | Event::ToolArgsDelta { .. }
+                | Event::ReasoningStarted { .. }
+                | Event::ReasoningDelta { .. }
+                | Event::ReasoningDone { .. }
+                | Event::StepStarted
+                | Event::StepFinished
+                | Event::Usage(_)
+                | Event::Retry { .. }
+                | Event::Fallback { .. }
+                | Event::Finished { .. } => None,
+            })
+            .collect();
+        assert_eq!(results.len(), 3, "{events:#?}");
+        assert_eq!(results.last(), Some(&(0, &json!(2_i32).into())));
+    }
 
-                    // Accumulate tool calls
-                    if let Some(tool_call_deltas) = chat_choice.delta.tool_calls {
-                        for tool_call_delta in tool_call_deltas {
-                            match tool_deltas.entry(tool_call_delta.index) {
-                                btree_map::Entry::Vacant(vacant_entry) => {
-                                    let index = assistant_message.lock().await.parts.len();
-                                    assistant_message
-                                        .lock()
-                                        .await
-                                        .parts
-                                        .push(message::Part::Tool(message::ToolPart {
-                                            tool: message::ToolCall {
-                                                id: tool_call_delta.id.unwrap_or_default(),
-                                                name: tool_call_delta
-                                                    .function
-                                                    .expect("first part always has a function")
-                                                    .name
-                                                    .unwrap_or_default(),
-                                                args: Value::Null,
-                                                result: None,
-                                            },
-                                        }));
-                                    vacant_entry.insert((String::new(), index));
-                                }
-                                btree_map::Entry::Occupied(mut occupied_entry) => {
-                                    let &mut (ref mut args, part_index) = occupied_entry.get_mut();
-
-                                    let mut assistant_message = assistant_message.lock().await;
-                                    let part = assistant_message
-                                        .parts
-                                        .get_mut(part_index)
-                                        .expect("part to exist");
-                                    let_assert!(&mut message::Part::Tool(message::ToolPart { ref mut tool }) = part);
-                                    if let Some(function) = tool_call_delta.function {
-                                        args.push_str(&function.arguments.unwrap_or_default());
-                                        tool.args = parse_incomplete_json(args).unwrap_or_default();
-                                    }
-
-                                    let Some(tool_executor) = tools.get(&tool.name) else {
-                                        continue;
-                                    };
-                                    if !tool_executor.is_streamable() {
-                                        continue;
-                                    }
-
-                                    if let Some(future) =
-                                        tool_executor.execute(tool.id.clone(), tool.args.clone())
-                                    {
-                                        tool_executions
-                                            .spawn(future.map(move |result| (part_index, result)));
-                                    }
-                                    drop(assistant_message);
-                                }
-                            }
-                        }
-                    }
+    #[tokio::test]
+    async fn stops_after_steps() {
+        let add = || {
+            vec![
+                provider::Event::ToolCallStarted {
+                    index: 0,
+                    id: String::from("call"),
+                    name: String::from("add"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 0,
+                    delta: String::from(r#"{"left": 1, "right": 2}"#),
+                },
+                provider::Event::ToolCallDone { index: 0 },
+            ]
+        };
+        let finish_reason = async |config: GenerateConfig| {
+            let mut session = Session::default();
+            let events: Vec<Event> = provider_events(
+                Scripted::new(vec![add(), add(), add()]),
+                &mut session,
+                &[],
+                add_tool(),
+                Some(config),
+                CancellationToken::new(),
+            )
+            .try_collect()
+            .await
+            .expect("stream to succeed");
+            let steps = events
+                .iter()
+                .filter(|event| matches!(**event, Event::ToolResult { .. }))
+                .count();
+            (steps, events.last().cloned())
+        };
+
+        assert_eq!(
+            finish_reason(GenerateConfig {
+                max_steps: Some(2),
+                ..GenerateConfig::default()
+            })
+            .await,
+            (
+                2,
+                Some(Event::Finished {
+                    reason: FinishReason::MaxSteps
+                })
+            )
+        );
+        assert_eq!(
+            finish_reason(GenerateConfig {
+                stop_conditions: vec![config::StopCondition::tool_called("add")],
+                ..GenerateConfig::default()
+            })
+            .await,
+            (
+                1,
+                Some(Event::Finished {
+                    reason: FinishReason::StopCondition
+                })
+            )
+        );
+    }
 
-                    co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
-                        .await;
-                }
+    #[tokio::test]
+    async fn enforces_budgets() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::ToolCallStarted {
+                index: 0,
+                id: String::from("call"),
+                name: String::from("add"),
+            },
+            provider::Event::ToolArgsDelta {
+                index: 0,
+                delta: String::from(r#"{"left": 1, "right": 2}"#),
+            },
+            provider::Event::ToolCallDone { index: 0 },
+            provider::Event::Usage(Usage {
+                input_tokens: BigDecimal::from(1_000_u32),
+                ..Usage::default()
+            }),
+        ]]);
+        let mut session = Session {
+            budget: Some(Budget {
+                max_tokens: Some(1_000),
+                ..Budget::default()
+            }),
+            ..Session::default()
+        };
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            add_tool(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::BudgetExceeded(BudgetExceeded::Tokens {
+                    used: 1_000,
+                    ..
+                }))
+            ),
+            "{result:#?}"
+        );
+        assert_eq!(session.usage.input_tokens, BigDecimal::from(1_000_u32));
+
+        // The pre-flight estimate prevents calls that cannot fit, even though
+        // this one would succeed.
+        let provider = Scripted::new(vec![Vec::new()]);
+        let mut session = Session {
+            budget: Some(Budget {
+                max_tokens: Some(1),
+                preflight: true,
+                ..Budget::default()
+            }),
+            ..Session::default()
+        };
+        let messages = [Message {
+            id: String::from("1"),
+            role: message::Role::User,
+            parts: vec![message::Part::Text(message::TextPart {
+                text: String::from("Hello"),
+            })],
+            model: None,
+        }];
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &messages,
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(matches!(result, Err(Error::BudgetExceeded(_))));
+
+        // Images are not counted as text.
+        let provider = Scripted::new(vec![Vec::new()]);
+        let mut session = Session {
+            budget: Some(Budget {
+                max_tokens: Some(1_000),
+                preflight: true,
+                ..Budget::default()
+            }),
+            ..Session::default()
+        };
+        let messages = [Message {
+            id: String::from("1"),
+            role: message::Role::User,
+            parts: vec![message::Part::Image(message::ImagePart {
+                source: message::Source::Base64 {
+                    media_type: String::from("image/png"),
+                    data: "A".repeat(100_000),
+                },
+            })],
+            model: None,
+        }];
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &messages,
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(result.is_ok(), "{result:#?}");
+
+        // A token counter replaces the estimate, e.g. to count images.
+        let provider = Scripted::new(vec![Vec::new()]);
+        let mut session = Session {
+            budget: Some(Budget {
+                max_tokens: Some(1_000),
+                preflight: true,
+                token_counter: Some(TokenCounter::new(|messages, _| {
+                    u64::try_from(messages.len()).unwrap_or(u64::MAX) * 1_500
+                })),
+                ..Budget::default()
+            }),
+            ..Session::default()
+        };
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &messages,
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::BudgetExceeded(BudgetExceeded::Tokens {
+                    estimated: 1_500,
+                    ..
+                }))
+            ),
+            "{result:#?}"
+        );
+    }
 
-                if is_last_chunk {
-                    if let Some(completion_usage) = response.usage {
-                        let cached_input_tokens = completion_usage
-                            .prompt_tokens_details
-                            .and_then(|details| details.cached_tokens)
-                            .unwrap_or_default();
-                        session.cost += config.model.cost(&Usage {
-                            cached_input_tokens: cached_input_tokens.into(),
-                            input_tokens: completion_usage
-                                .prompt_tokens
-                                .saturating_sub(cached_input_tokens)
-                                .into(),
-                            output_tokens: completion_usage.completion_tokens.into(),
-                        });
-                    }
-                }
-            }
+    #[tokio::test]
+    async fn records_failed_steps() {
+        let usage = Usage {
+            input_tokens: BigDecimal::from(10_u32),
+            ..Usage::default()
+        };
+        let provider = Scripted::with_results(vec![vec![
+            Ok(provider::Event::Usage(usage.clone())),
+            Err(ApiError::new(None, None, String::from("Stream interrupted")).into()),
+        ]]);
+        let mut session = Session::default();
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(matches!(result, Err(Error::Api(_))), "{result:#?}");
+
+        let [ref step] = *session.steps else {
+            panic!("expected one step, got {}", session.steps.len());
+        };
+        assert_eq!(step.usage, usage);
+        assert_eq!(step.cost, session.cost);
+        let model = GenerateConfig::default().model;
+        let model_usage = session
+            .models
+            .get(&model.to_string())
+            .expect("usage of the model to be recorded");
+        assert_eq!(model_usage.calls, 1);
+        assert_eq!(model_usage.usage, usage);
+    }
 
-            for (_, (_, part_index)) in tool_deltas {
-                let mut assistant_message = assistant_message.lock().await;
-                let part = assistant_message
-                    .parts
-                    .get_mut(part_index)
-                    .expect("part to exist");
-                let_assert!(
-                    &mut message::Part::Tool(message::ToolPart {
-                        ref mut tool,
-                    }) = part
-                );
+    #[tokio::test]
+    async fn records_usage() {
+        let usage = || {
+            provider::Event::Usage(Usage {
+                input_tokens: BigDecimal::from(10_u32),
+                output_tokens: BigDecimal::from(5_u32),
+                ..Usage::default()
+            })
+        };
+        let provider = Scripted::new(vec![
+            vec![
+                provider::Event::ToolCallStarted {
+                    index: 0,
+                    id: String::from("call"),
+                    name: String::from("add"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 0,
+                    delta: String::from(r#"{"left": 1, "right": 2}"#),
+                },
+                provider::Event::ToolCallDone { index: 0 },
+                usage(),
+            ],
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("3"),
+                },
+                usage(),
+            ],
+        ]);
+        let mut session = Session::default();
+        let _events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            add_tool(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("generation to succeed");
+
+        assert_eq!(session.usage.input_tokens, BigDecimal::from(20_u32));
+        assert_eq!(
+            session.tool_calls,
+            BTreeMap::from([(String::from("add"), 1)])
+        );
+        let model = GenerateConfig::default().model;
+        let model_usage = session
+            .models
+            .get(&model.to_string())
+            .expect("usage of the model to be recorded");
+        assert_eq!(model_usage.calls, 2);
+        assert_eq!(model_usage.usage, session.usage);
+        assert_eq!(model_usage.cost, session.cost);
+
+        let [ref first, ref second] = *session.steps else {
+            panic!("expected two steps, got {}", session.steps.len());
+        };
+        assert_eq!(first.model, model);
+        assert_eq!(first.tool_calls, 1);
+        assert_eq!(second.tool_calls, 0);
+        assert_eq!(second.usage.output_tokens, BigDecimal::from(5_u32));
+        assert!(first.started_at <= second.started_at);
+        assert!(session.steps.iter().all(|step| step.latency.is_some()));
+    }
 
-                let Some(tool_executor) = tools.get(&tool.name) else {
-                    tool.result = Some(json!(format!("No such tool: {}", tool.name)));
-                    continue;
-                };
-                if tool_executor.is_streamable() {
-                    continue;
-                }
+    #[tokio::test]
+    async fn generates_messages() {
+        let usage = || {
+            provider::Event::Usage(Usage {
+                input_tokens: BigDecimal::from(10_u32),
+                output_tokens: BigDecimal::from(5_u32),
+                ..Usage::default()
+            })
+        };
+        let provider = Scripted::new(vec![
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("Adding."),
+                },
+                provider::Event::ToolCallStarted {
+                    index: 1,
+                    id: String::from("call"),
+                    name: String::from("add"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 1,
+                    delta: String::from(r#"{"left": 1, "right": 2}"#),
+                },
+                provider::Event::ToolCallDone { index: 1 },
+                usage(),
+            ],
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("It is 3."),
+                },
+                usage(),
+            ],
+        ]);
+        let mut session = Session {
+            usage: Usage {
+                input_tokens: BigDecimal::from(100_u32),
+                ..Usage::default()
+            },
+            ..Session::default()
+        };
+
+        let generation = provider_generate(
+            provider,
+            &mut session,
+            &[],
+            add_tool(),
+            None,
+            CancellationToken::new(),
+        )
+        .await
+        .expect("generation to succeed");
+
+        assert_eq!(generation.finish_reason, FinishReason::Stop);
+        assert_eq!(generation.text(), "It is 3.");
+        assert_eq!(generation.message.parts.len(), 3);
+        assert_eq!(generation.steps, session.steps);
+        assert_eq!(generation.usage.input_tokens, BigDecimal::from(20_u32));
+        assert_eq!(session.usage.input_tokens, BigDecimal::from(120_u32));
+    }
 
-                if let Some(future) = tool_executor.execute(tool.id.clone(), tool.args.clone()) {
-                    tool_executions.spawn(future.map(move |result| (part_index, result)));
-                }
-                drop(assistant_message);
-            }
+    #[tokio::test]
+    async fn finishes_incomplete_responses() {
+        let provider = Scripted::new(vec![vec![
+            provider::Event::TextDelta {
+                index: 0,
+                delta: String::from("The answer is"),
+            },
+            provider::Event::Usage(Usage {
+                output_tokens: BigDecimal::from(16_u32),
+                ..Usage::default()
+            }),
+            provider::Event::Incomplete(FinishReason::Length),
+        ]]);
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("generation to succeed");
+
+        assert_eq!(
+            events.last(),
+            Some(&Event::Finished {
+                reason: FinishReason::Length
+            })
+        );
+        assert_eq!(session.usage.output_tokens, BigDecimal::from(16_u32));
+        assert_eq!(session.steps.len(), 1);
+    }
 
-            if tool_executions.is_empty() {
-                return;
-            }
+    #[test]
+    fn deserializes_legacy_cursors() {
+        let session: Session = serde_json::from_value(json!({
+            "cursor": "resp_1",
+            "cost": "0.5",
+        }))
+        .expect("session to deserialize");
+        assert_eq!(
+            session.cursor,
+            Some(Cursor {
+                response_id: String::from("resp_1"),
+                message_id: String::new(),
+                part_index: 0,
+            })
+        );
+
+        let json = serde_json::to_value(&session).expect("session to serialize");
+        let session_again: Session =
+            serde_json::from_value(json).expect("session to deserialize again");
+        assert_eq!(session_again, session);
+    }
 
-            while let Some((part_index, result)) = tool_executions
-                .join_next()
-                .await
-                .transpose()
-                .expect("tool to be executed")
-            {
-                let result = match result {
-                    Ok(result) => Some(result),
-                    Err(error) => Some(json!(format!("Error: {error}"))),
-                };
-                let mut assistant_message = assistant_message.lock().await;
-                let part = assistant_message
-                    .parts
-                    .get_mut(part_index)
-                    .expect("part to exist");
-                let_assert!(
-                    &mut message::Part::Tool(message::ToolPart {
-                        ref mut tool,
-                    }) = part,
-                );
-                tool.result = result;
-                drop(assistant_message);
-            }
+    #[tokio::test]
+    async fn sets_cursor() {
+        let provider = Scripted::new(vec![
+            vec![
+                provider::Event::ToolCallStarted {
+                    index: 0,
+                    id: String::from("call"),
+                    name: String::from("add"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 0,
+                    delta: String::from(r#"{"left": 1, "right": 2}"#),
+                },
+                provider::Event::ToolCallDone { index: 0 },
+                provider::Event::Cursor(String::from("resp_1")),
+            ],
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("3"),
+                },
+                provider::Event::Cursor(String::from("resp_2")),
+            ],
+        ]);
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            add_tool(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("generation to succeed");
+        let Some(&Event::MessageStarted { ref id, .. }) = events.first() else {
+            panic!("expected the message to start first, got {events:#?}");
+        };
+
+        // The second response wrote the text after the tool call.
+        assert_eq!(
+            session.cursor,
+            Some(Cursor {
+                response_id: String::from("resp_2"),
+                message_id: id.clone(),
+                part_index: 1,
+            })
+        );
+    }
 
-            co.yield_(Ok(Arc::clone(&assistant_message).lock_owned().await))
-                .await;
+    #[tokio::test]
+    async fn fails_on_out_of_order_events() {
+        let provider = Scripted::new(vec![vec![provider::Event::ToolArgsDelta {
+            index: 0,
+            delta: String::from("{}"),
+        }]]);
+        let mut session = Session::default();
+        let result: Result<Vec<Event>, Error> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await;
+        assert!(matches!(result, Err(Error::UnexpectedEvent(_))));
+    }
 
-            if assistant_message
-                .lock()
-                .await
-                .tool_calls()
-                .filter(|tool_call| tool_call.result.is_none())
-                .count()
-                > 0
-            {
-                // There are some client tool calls that need to be executed.
-                return;
+    #[tokio::test]
+    async fn retries_before_output() {
+        let rate_limited = || {
+            Err(ApiError {
+                retry_after: Some(Duration::from_millis(1)),
+                ..ApiError::new(Some(429), None, String::from("Slow down"))
             }
+            .into())
+        };
+        let text = |delta: &str| {
+            Ok(provider::Event::TextDelta {
+                index: 0,
+                delta: delta.to_owned(),
+            })
+        };
+        let provider = Scripted::with_results(vec![
+            vec![rate_limited()],
+            vec![text("Hello"), rate_limited()],
+        ]);
+        let mut session = Session::default();
+        let events: Vec<Result<Event, Error>> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            None,
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+
+        assert!(
+            matches!(
+                events.get(2),
+                Some(&Ok(Event::Retry { attempt: 1, delay, .. })) if delay == Duration::from_millis(1)
+            ),
+            "{events:#?}"
+        );
+        assert!(matches!(
+            events.get(3),
+            Some(&Ok(Event::TextDelta { part_index: 0, .. }))
+        ));
+        // Errors after the first event are not retried.
+        assert!(matches!(events.get(4), Some(&Err(Error::Api(_)))));
+        assert_eq!(events.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_next_model() {
+        let usage = Usage {
+            input_tokens: BigDecimal::from(1_000_000_u32),
+            ..Usage::default()
+        };
+        let provider = Scripted::with_results(vec![
+            vec![Err(ApiError::new(
+                Some(503),
+                None,
+                String::from("Unavailable"),
+            )
+            .into())],
+            vec![
+                Ok(provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("Hello"),
+                }),
+                Ok(provider::Event::Usage(usage.clone())),
+            ],
+        ]);
+        let config = GenerateConfig {
+            model: Model::O3,
+            retry: config::Retry::never(),
+            fallbacks: vec![Model::Gpt4_1Mini.into()],
+            ..GenerateConfig::default()
+        };
+        let mut session = Session::default();
+        let events: Vec<Event> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            Some(config),
+            CancellationToken::new(),
+        )
+        .try_collect()
+        .await
+        .expect("stream to succeed");
+
+        assert!(
+            events.iter().any(|event| matches!(
+                *event,
+                Event::Fallback { ref model, .. } if *model == Model::Gpt4_1Mini
+            )),
+            "{events:#?}"
+        );
+        let mut message = Message {
+            id: String::new(),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        };
+        let mut accumulator = event::Accumulator::default();
+        for event in &events {
+            accumulator
+                .apply(&mut message, event)
+                .expect("event to apply");
         }
-    })
+        assert_eq!(message.model, Some(Model::Gpt4_1Mini));
+        assert_eq!(session.cost, Model::Gpt4_1Mini.cost(&usage));
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_requests_despite_fallbacks() {
+        let provider = Scripted::with_results(vec![vec![Err(ApiError::new(
+            Some(400),
+            None,
+            String::from("Invalid value"),
+        )
+        .into())]]);
+        let config = GenerateConfig {
+            retry: config::Retry::never(),
+            fallbacks: vec![Model::Gpt4_1Mini.into()],
+            ..GenerateConfig::default()
+        };
+        let mut session = Session::default();
+        let events: Vec<_> = provider_events(
+            provider,
+            &mut session,
+            &[],
+            tool::Set::default(),
+            Some(config),
+            CancellationToken::new(),
+        )
+        .collect()
+        .await;
+
+        assert!(
+            !events
+                .iter()
+                .any(|event| matches!(*event, Ok(Event::Fallback { .. }))),
+            "{events:#?}"
+        );
+        assert!(matches!(
+            events.last(),
+            Some(&Err(Error::Api(ApiError {
+                kind: ApiErrorKind::InvalidRequest,
+                ..
+            })))
+        ));
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let retry = config::Retry {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3),
+            jitter: false,
+        };
+        let error = Error::Other(String::from("Connection reset"));
+        assert_eq!(retry.delay(1, &error), Duration::from_secs(1));
+        assert_eq!(retry.delay(2, &error), Duration::from_secs(2));
+        assert_eq!(retry.delay(3, &error), Duration::from_secs(3));
+
+        let retry = config::Retry {
+            jitter: true,
+            ..retry
+        };
+        let delay = retry.delay(2, &error);
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+
+        let rate_limited = |retry_after| {
+            Error::Api(ApiError {
+                retry_after: Some(retry_after),
+                ..ApiError::new(Some(429), None, String::from("Slow down"))
+            })
+        };
+        assert_eq!(
+            retry.delay(1, &rate_limited(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            retry.delay(1, &rate_limited(Duration::from_hours(1))),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[test]
+    fn deserializes_configs_without_client() {
+        let config: GenerateConfig =
+            serde_json::from_value(json!({"model": "gpt-4.1", "tool_choice": "Auto"}))
+                .expect("config to deserialize");
+        assert_eq!(config.client, config::Client::default());
+        assert_eq!(config.max_output_tokens, None);
+        let value = serde_json::to_value(&config).expect("config to serialize");
+        assert_eq!(value.get("client"), None);
+    }
+
+    #[test]
+    fn deserializes_unknown_models_by_name() {
+        let model: Model = serde_json::from_str(r#""llama3.2""#).expect("model to deserialize");
+        assert_eq!(model, Model::Other(String::from("llama3.2")));
+        assert_eq!(model.to_string(), "llama3.2");
+        assert_eq!(
+            serde_json::to_string(&model).expect("model to serialize"),
+            r#""llama3.2""#
+        );
+
+        let model: Model = serde_json::from_str(r#""gpt-4.1-mini""#).expect("model to deserialize");
+        assert_eq!(model, Model::Gpt4_1Mini);
+    }
+
+    #[test]
+    fn prices_custom_models() {
+        let model = Model::Custom {
+            name: String::from("ft:gpt-4o-mini:acme::abc123"),
+            pricing: Pricing {
+                input: BigDecimal::from(3_u32),
+                cached_input: BigDecimal::from(1_u32),
+                output: BigDecimal::from(12_u32),
+                ..Pricing::default()
+            },
+        };
+        assert_eq!(model.to_string(), "ft:gpt-4o-mini:acme::abc123");
+
+        let usage = Usage {
+            cached_input_tokens: BigDecimal::from(1_000_000_u32),
+            input_tokens: BigDecimal::from(500_000_u32),
+            output_tokens: BigDecimal::from(250_000_u32),
+            ..Usage::default()
+        };
+        assert_eq!(
+            model.cost(&usage),
+            "5.5".parse::<BigDecimal>().expect("decimal to parse")
+        );
+
+        let other = Model::Other(String::from("llama3.2"));
+        assert_eq!(other.cost(&usage), BigDecimal::from(0_u32));
+    }
 }
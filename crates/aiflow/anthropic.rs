@@ -0,0 +1,1312 @@
+//! Provider for the Anthropic Messages API.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use futures::{StreamExt as _, stream::BoxStream};
+use genawaiter::sync::Gen;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+    ApiError, Error, FinishReason, Message, Tool, Usage, config, message,
+    provider::{self, Provider},
+    util::server_sent_events,
+};
+
+/// Base URL of the official Anthropic API.
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com/v1";
+
+/// Name of the provider recorded in the reasoning parts it produces.
+const PROVIDER: &str = "anthropic";
+
+/// Version of the Messages API the wire types follow.
+const API_VERSION: &str = "2023-06-01";
+
+/// The Messages API requires an output limit, so we use this one when none is configured.
+const DEFAULT_MAX_OUTPUT_TOKENS: u32 = 8192;
+
+/// Thinking budgets for each reasoning effort. The API requires at least 1024.
+const LOW_THINKING_BUDGET: u32 = 1_024;
+const MEDIUM_THINKING_BUDGET: u32 = 4_000;
+const HIGH_THINKING_BUDGET: u32 = 16_000;
+
+/// Provider for the Anthropic Messages API.
+///
+/// The endpoint is configured through
+/// [`GenerateConfig::client`](crate::GenerateConfig::client). The API key
+/// defaults to the `ANTHROPIC_API_KEY` environment variable.
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    client: reqwest::Client,
+}
+
+impl Messages {
+    /// Creates a provider.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a `POST` request to the Messages API.
+    ///
+    /// Invalid header names or values are reported when the request is sent.
+    fn post(&self, config: &config::Client) -> reqwest::RequestBuilder {
+        let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        let mut request = self
+            .client
+            .post(format!("{}/messages", base_url.trim_end_matches('/')))
+            .header("anthropic-version", API_VERSION);
+        if let Some(api_key) = config
+            .api_key
+            .clone()
+            .or_else(|| std::env::var("ANTHROPIC_API_KEY").ok())
+        {
+            request = request.header("x-api-key", api_key);
+        }
+        for (name, value) in &config.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+impl Provider for Messages {
+    fn stream<'request>(
+        &'request self,
+        request: provider::Request<'request>,
+    ) -> BoxStream<'request, Result<provider::Event, Error>> {
+        Gen::new(move |co| async move {
+            let thread = match Thread::try_from(request.messages) {
+                Ok(thread) => thread,
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+
+            let tools = request.tools.values().map(Into::into).collect::<Vec<_>>();
+            let tool_choice = (!tools.is_empty()).then_some(match request.config.tool_choice {
+                config::ToolChoice::Auto => ToolChoice::Auto {
+                    disable_parallel_tool_use: !request.config.parallel_tool_calls,
+                },
+                config::ToolChoice::Required => ToolChoice::Any {
+                    disable_parallel_tool_use: !request.config.parallel_tool_calls,
+                },
+                config::ToolChoice::None => ToolChoice::None,
+            });
+
+            // The Messages API has no structured outputs, so the model is
+            // asked to follow the schema instead.
+            let system = match request.config.response_format {
+                Some(ref format) => Some(
+                    thread
+                        .system
+                        .into_iter()
+                        .chain([format!(
+                            "Respond only with JSON that matches this schema:\n{}",
+                            format.schema
+                        )])
+                        .collect::<Vec<_>>()
+                        .join("\n\n"),
+                ),
+                None => thread.system,
+            };
+
+            let thinking = match thinking(request.config, tool_choice.as_ref()) {
+                Ok(thinking) => thinking,
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+
+            let body = Request {
+                model: request.config.model.to_string(),
+                // Thinking counts towards the limit, so the default leaves room for it.
+                max_tokens: request.config.max_output_tokens.unwrap_or_else(|| {
+                    DEFAULT_MAX_OUTPUT_TOKENS.saturating_add(match thinking {
+                        Some(Thinking::Enabled { budget_tokens }) => budget_tokens,
+                        None => 0,
+                    })
+                }),
+                thinking,
+                system,
+                messages: thread.messages,
+                tools,
+                tool_choice,
+                stream: true,
+            };
+
+            let mut stream = server_sent_events(self.post(&request.config.client).json(&body));
+
+            let mut mapper = EventMapper::default();
+
+            while let Some(result) = stream.next().await {
+                let data = match result {
+                    Ok(data) => data,
+                    Err(error) => {
+                        co.yield_(Err(error)).await;
+                        return;
+                    }
+                };
+
+                match serde_json::from_str(&data)
+                    .map_err(Into::into)
+                    .and_then(|event| mapper.map(event))
+                {
+                    Ok(events) => {
+                        for event in events {
+                            co.yield_(Ok(event)).await;
+                        }
+                    }
+                    Err(error) => {
+                        co.yield_(Err(error)).await;
+                        return;
+                    }
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// A Messages API request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Request {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<InputMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    stream: bool,
+}
+
+/// Extended thinking settings in a Messages API request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Thinking {
+    Enabled { budget_tokens: u32 },
+}
+
+/// Returns the thinking settings of a request.
+///
+/// Thinking is enabled when reasoning is configured, with a budget that
+/// follows the effort and stays below the output limit. Settings that the API
+/// rejects fail the request instead of being ignored: models that are known
+/// not to reason, output limits that leave no room for the minimum budget and
+/// required tool calls.
+fn thinking(
+    config: &crate::GenerateConfig,
+    tool_choice: Option<&ToolChoice>,
+) -> Result<Option<Thinking>, Error> {
+    if config.reasoning == config::Reasoning::default() {
+        return Ok(None);
+    }
+    if config.model.reasons() == Some(false) {
+        return Err(Error::InvalidConfig(format!(
+            "{} does not support reasoning",
+            config.model
+        )));
+    }
+    if let Some(&ToolChoice::Any { .. }) = tool_choice {
+        return Err(Error::InvalidConfig(String::from(
+            "Reasoning cannot be combined with a required tool choice",
+        )));
+    }
+    let budget_tokens = match config.reasoning.effort {
+        Some(config::ReasoningEffort::Low) => LOW_THINKING_BUDGET,
+        Some(config::ReasoningEffort::Medium) | None => MEDIUM_THINKING_BUDGET,
+        Some(config::ReasoningEffort::High) => HIGH_THINKING_BUDGET,
+    };
+    let budget_tokens = match config.max_output_tokens {
+        Some(max_output_tokens) if max_output_tokens <= LOW_THINKING_BUDGET => {
+            return Err(Error::InvalidConfig(format!(
+                "Reasoning requires more than {LOW_THINKING_BUDGET} output tokens, but the limit is {max_output_tokens}"
+            )));
+        }
+        Some(max_output_tokens) => budget_tokens.min(max_output_tokens.saturating_sub(1)),
+        None => budget_tokens,
+    };
+    Ok(Some(Thinking::Enabled { budget_tokens }))
+}
+
+/// The system prompt and messages of a request.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Thread {
+    system: Option<String>,
+    messages: Vec<InputMessage>,
+}
+
+/// A message in a Messages API request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct InputMessage {
+    role: Role,
+    content: Vec<ContentBlock>,
+}
+
+/// The role of a message in a Messages API request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Role {
+    User,
+    Assistant,
+}
+
+/// A content block in a Messages API request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: message::Source,
+    },
+    Document {
+        source: message::Source,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: ToolResultContent,
+    },
+    Thinking {
+        thinking: String,
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+}
+
+/// The content of a `tool_result` block.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ContentBlock>),
+}
+
+/// Conversion from `ToolOutput` to the content of a `tool_result` block.
+///
+/// Outputs without images and files are sent as text.
+impl From<message::ToolOutput> for ToolResultContent {
+    fn from(val: message::ToolOutput) -> Self {
+        if val.is_text() {
+            return Self::Text(val.to_text());
+        }
+        Self::Blocks(
+            val.into_content()
+                .into_iter()
+                .map(|content| match content {
+                    message::ToolContent::Text { text } => ContentBlock::Text { text },
+                    message::ToolContent::Image(image_part) => ContentBlock::Image {
+                        source: image_part.source,
+                    },
+                    message::ToolContent::File(file_part) => ContentBlock::Document {
+                        source: file_part.source,
+                        title: file_part.filename,
+                    },
+                })
+                .collect(),
+        )
+    }
+}
+
+/// A tool definition in a Messages API request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ToolDefinition {
+    name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    description: String,
+    input_schema: Value,
+}
+
+/// Tool selection strategy in a Messages API request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ToolChoice {
+    Auto { disable_parallel_tool_use: bool },
+    Any { disable_parallel_tool_use: bool },
+    None,
+}
+
+/// Conversion from a thread of `Message`s to a system prompt and Messages API messages.
+///
+/// Leading developer messages become the system prompt. Later developer
+/// messages are sent as user messages, since the API has no developer role.
+/// Consecutive messages with the same role are merged.
+impl TryFrom<&[Message]> for Thread {
+    type Error = Error;
+
+    fn try_from(val: &[Message]) -> Result<Self, Self::Error> {
+        let mut thread = Self::default();
+
+        let system_messages = val
+            .iter()
+            .take_while(|message| message.role == message::Role::Developer)
+            .count();
+        let (system, messages) = val.split_at(system_messages);
+
+        let system = system
+            .iter()
+            .flat_map(|message| &message.parts)
+            .filter_map(|part| match *part {
+                message::Part::Text(ref text_part) => Some(Ok(text_part.text.clone())),
+                message::Part::Error(ref error_part) => Some(Ok(error_part.error.to_string())),
+                message::Part::Image(_) => Some(Err(Error::InvalidMessages(String::from(
+                    "Image part must be a user message",
+                )))),
+                message::Part::File(_) => Some(Err(Error::InvalidMessages(String::from(
+                    "File part must be a user message",
+                )))),
+                message::Part::Tool(_) | message::Part::Reasoning(_) => None,
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !system.is_empty() {
+            thread.system = Some(system.join("\n\n"));
+        }
+
+        for message in messages.iter().cloned() {
+            for input_message in Vec::<InputMessage>::try_from(message)? {
+                match thread.messages.last_mut() {
+                    Some(last) if last.role == input_message.role => {
+                        last.content.extend(input_message.content);
+                    }
+                    _ => thread.messages.push(input_message),
+                }
+            }
+        }
+
+        Ok(thread)
+    }
+}
+
+/// Conversion from `Message` to a list of Messages API messages.
+///
+/// Tool results are sent in a user message following the assistant message
+/// that contains the matching `tool_use` blocks.
+impl TryFrom<Message> for Vec<InputMessage> {
+    type Error = Error;
+
+    fn try_from(val: Message) -> Result<Self, Self::Error> {
+        let role = match val.role {
+            message::Role::Developer | message::Role::User => Role::User,
+            message::Role::Assistant => Role::Assistant,
+        };
+
+        let mut messages = Self::new();
+        let mut content = Vec::new();
+        let mut tool_results = Vec::new();
+        for part in val.parts {
+            match part {
+                message::Part::Text(text_part) => {
+                    flush_tool_results(&mut messages, role, &mut content, &mut tool_results);
+                    // The API rejects empty text blocks.
+                    if !text_part.text.is_empty() {
+                        content.push(ContentBlock::Text {
+                            text: text_part.text,
+                        });
+                    }
+                }
+                message::Part::Image(image_part) => {
+                    if val.role != message::Role::User {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Image part must be a user message",
+                        )));
+                    }
+                    content.push(ContentBlock::Image {
+                        source: image_part.source,
+                    });
+                }
+                message::Part::File(file_part) => {
+                    if val.role != message::Role::User {
+                        return Err(Error::InvalidMessages(String::from(
+                            "File part must be a user message",
+                        )));
+                    }
+                    content.push(ContentBlock::Document {
+                        source: file_part.source,
+                        title: file_part.filename,
+                    });
+                }
+                message::Part::Tool(tool_part) => {
+                    if val.role != message::Role::Assistant {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Tool part must be an assistant message",
+                        )));
+                    }
+                    let tool = tool_part.tool;
+                    if let Some(output) = tool.result {
+                        tool_results.push(ContentBlock::ToolResult {
+                            tool_use_id: tool.id.clone(),
+                            content: output.into(),
+                        });
+                    }
+                    content.push(ContentBlock::ToolUse {
+                        id: tool.id,
+                        name: tool.name,
+                        input: if tool.args.is_null() {
+                            json!({})
+                        } else {
+                            tool.args
+                        },
+                    });
+                }
+                // Reasoning of other providers cannot be sent to Anthropic.
+                message::Part::Reasoning(message::ReasoningPart {
+                    text,
+                    encrypted_content: Some(signature),
+                    provider: Some(provider),
+                    ..
+                }) if provider == PROVIDER => {
+                    // Thinking starts a new turn after the results of tool calls.
+                    flush_tool_results(&mut messages, role, &mut content, &mut tool_results);
+                    // Redacted thinking has no text.
+                    content.push(if text.is_empty() {
+                        ContentBlock::RedactedThinking { data: signature }
+                    } else {
+                        ContentBlock::Thinking {
+                            thinking: text,
+                            signature,
+                        }
+                    });
+                }
+                message::Part::Reasoning(_) => {}
+                message::Part::Error(error_part) => {
+                    if val.role != message::Role::Developer {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Error part must be a developer message",
+                        )));
+                    }
+                    content.push(ContentBlock::Text {
+                        text: error_part.error.to_string(),
+                    });
+                }
+            }
+        }
+        if !content.is_empty() {
+            messages.push(InputMessage { role, content });
+        }
+        if !tool_results.is_empty() {
+            messages.push(InputMessage {
+                role: Role::User,
+                content: tool_results,
+            });
+        }
+        Ok(messages)
+    }
+}
+
+/// Ends the current message and sends the results of its tool calls, if any.
+fn flush_tool_results(
+    messages: &mut Vec<InputMessage>,
+    role: Role,
+    content: &mut Vec<ContentBlock>,
+    tool_results: &mut Vec<ContentBlock>,
+) {
+    if tool_results.is_empty() {
+        return;
+    }
+    messages.push(InputMessage {
+        role,
+        content: core::mem::take(content),
+    });
+    messages.push(InputMessage {
+        role: Role::User,
+        content: core::mem::take(tool_results),
+    });
+}
+
+/// Conversion from `Tool` to a Messages API tool definition.
+impl From<&Tool> for ToolDefinition {
+    fn from(val: &Tool) -> Self {
+        Self {
+            name: val.name().to_owned(),
+            description: val.description().to_owned(),
+            input_schema: val.parameters().clone().into(),
+        }
+    }
+}
+
+/// An event in a Messages API stream.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StreamMessage,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: StreamContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: StreamDelta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    MessageDelta {
+        delta: MessageDelta,
+        usage: StreamUsage,
+    },
+    MessageStop,
+    Error {
+        error: StreamError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// The changes to the message carried by a `message_delta` event.
+#[derive(Debug, Clone, Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+/// The message announced by a `message_start` event.
+#[derive(Debug, Clone, Deserialize)]
+struct StreamMessage {
+    usage: StreamUsage,
+}
+
+/// A content block announced by a `content_block_start` event.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+    },
+    Thinking {
+        thinking: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// A delta carried by a `content_block_delta` event.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// Token usage reported by `message_start` and `message_delta` events.
+///
+/// `message_delta` only reports the fields that changed.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[expect(clippy::struct_field_names, reason = "these are the API's field names")]
+struct StreamUsage {
+    input_tokens: Option<u64>,
+    cache_creation_input_tokens: Option<u64>,
+    cache_read_input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+/// An error reported in the middle of a stream.
+#[derive(Debug, Clone, Deserialize)]
+struct StreamError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+/// Maps Messages API stream events to provider events.
+#[derive(Debug, Default)]
+struct EventMapper {
+    tool_blocks: BTreeSet<usize>,
+    /// The last tool call that stopped. Its arguments are only complete if the
+    /// response was not cut off, which is known once another block starts or
+    /// the stop reason arrives.
+    stopped_tool: Option<usize>,
+    /// Signatures of the thinking blocks, or the data of redacted ones.
+    thinking_blocks: BTreeMap<usize, Option<String>>,
+    usage: StreamUsage,
+}
+
+impl EventMapper {
+    fn map(&mut self, event: StreamEvent) -> Result<Vec<provider::Event>, Error> {
+        let mut events = Vec::new();
+        if let StreamEvent::ContentBlockStart { .. } | StreamEvent::MessageStop = event
+            && let Some(index) = self.stopped_tool.take()
+        {
+            events.push(provider::Event::ToolCallDone { index });
+        }
+        events.extend(self.map_event(event)?);
+        Ok(events)
+    }
+
+    fn map_event(&mut self, event: StreamEvent) -> Result<Vec<provider::Event>, Error> {
+        Ok(match event {
+            StreamEvent::MessageStart { message } => {
+                self.usage = message.usage;
+                Vec::new()
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: StreamContentBlock::Text { text },
+            }
+            | StreamEvent::ContentBlockDelta {
+                index,
+                delta: StreamDelta::TextDelta { text },
+            } => vec![provider::Event::TextDelta { index, delta: text }],
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: StreamContentBlock::ToolUse { id, name },
+            } => {
+                self.tool_blocks.insert(index);
+                vec![provider::Event::ToolCallStarted { index, id, name }]
+            }
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: StreamDelta::InputJsonDelta { partial_json },
+            } if !partial_json.is_empty() => vec![provider::Event::ToolArgsDelta {
+                index,
+                delta: partial_json,
+            }],
+            StreamEvent::ContentBlockStop { index } if self.tool_blocks.contains(&index) => {
+                self.stopped_tool = Some(index);
+                Vec::new()
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: StreamContentBlock::Thinking { thinking },
+            } => {
+                self.thinking_blocks.insert(index, None);
+                let mut events = vec![provider::Event::ReasoningStarted {
+                    index,
+                    id: None,
+                    provider: String::from(PROVIDER),
+                }];
+                if !thinking.is_empty() {
+                    events.push(provider::Event::ReasoningDelta {
+                        index,
+                        delta: thinking,
+                    });
+                }
+                events
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block: StreamContentBlock::RedactedThinking { data },
+            } => {
+                self.thinking_blocks.insert(index, Some(data));
+                vec![provider::Event::ReasoningStarted {
+                    index,
+                    id: None,
+                    provider: String::from(PROVIDER),
+                }]
+            }
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: StreamDelta::ThinkingDelta { thinking },
+            } => vec![provider::Event::ReasoningDelta {
+                index,
+                delta: thinking,
+            }],
+            StreamEvent::ContentBlockDelta {
+                index,
+                delta: StreamDelta::SignatureDelta { signature },
+            } => {
+                if let Some(block) = self.thinking_blocks.get_mut(&index) {
+                    *block = Some(signature);
+                }
+                Vec::new()
+            }
+            StreamEvent::ContentBlockStop { index }
+                if self.thinking_blocks.contains_key(&index) =>
+            {
+                vec![provider::Event::ReasoningDone {
+                    index,
+                    encrypted_content: self.thinking_blocks.remove(&index).flatten(),
+                }]
+            }
+            StreamEvent::MessageDelta { delta, usage } => {
+                self.usage.input_tokens = usage.input_tokens.or(self.usage.input_tokens);
+                self.usage.cache_creation_input_tokens = usage
+                    .cache_creation_input_tokens
+                    .or(self.usage.cache_creation_input_tokens);
+                self.usage.cache_read_input_tokens = usage
+                    .cache_read_input_tokens
+                    .or(self.usage.cache_read_input_tokens);
+                self.usage.output_tokens = usage.output_tokens.or(self.usage.output_tokens);
+                // A tool call that stopped along with a cut off response is
+                // incomplete and must not be executed.
+                let stopped_tool = self.stopped_tool.take();
+                match delta.stop_reason.as_deref() {
+                    Some("max_tokens") => vec![provider::Event::Incomplete(FinishReason::Length)],
+                    Some("refusal") => {
+                        vec![provider::Event::Incomplete(FinishReason::ContentFilter)]
+                    }
+                    _ => stopped_tool
+                        .map(|index| provider::Event::ToolCallDone { index })
+                        .into_iter()
+                        .collect(),
+                }
+            }
+            StreamEvent::MessageStop => {
+                vec![provider::Event::Usage(Usage {
+                    cached_input_tokens: self
+                        .usage
+                        .cache_read_input_tokens
+                        .unwrap_or_default()
+                        .into(),
+                    // Cache writes are billed as input tokens.
+                    input_tokens: self
+                        .usage
+                        .input_tokens
+                        .unwrap_or_default()
+                        .saturating_add(self.usage.cache_creation_input_tokens.unwrap_or_default())
+                        .into(),
+                    output_tokens: self.usage.output_tokens.unwrap_or_default().into(),
+                    ..Usage::default()
+                })]
+            }
+            StreamEvent::Error { error } => {
+                return Err(ApiError::new(None, Some(error.kind), error.message).into());
+            }
+            StreamEvent::ContentBlockStart { .. }
+            | StreamEvent::ContentBlockDelta { .. }
+            | StreamEvent::ContentBlockStop { .. }
+            | StreamEvent::Other => Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::let_assert;
+
+    use super::*;
+    use crate::{ApiErrorKind, GenerateConfig, Model};
+
+    fn replay(fixture: &str) -> Result<Vec<provider::Event>, Error> {
+        let mut mapper = EventMapper::default();
+        let mut events = Vec::new();
+        for data in fixture
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+        {
+            events.extend(mapper.map(serde_json::from_str(data)?)?);
+        }
+        Ok(events)
+    }
+
+    #[test]
+    fn streams_text() {
+        let events =
+            replay(include_str!("anthropic/fixtures/text.sse")).expect("fixture to replay");
+        assert_eq!(
+            events,
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::new(),
+                },
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("Hello"),
+                },
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("!"),
+                },
+                provider::Event::Usage(Usage {
+                    cached_input_tokens: 10_u64.into(),
+                    input_tokens: 25_u64.into(),
+                    output_tokens: 15_u64.into(),
+                    ..Usage::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn streams_tool_use() {
+        let events =
+            replay(include_str!("anthropic/fixtures/tool_use.sse")).expect("fixture to replay");
+        assert_eq!(
+            events,
+            vec![
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::new(),
+                },
+                provider::Event::TextDelta {
+                    index: 0,
+                    delta: String::from("Let me check the weather."),
+                },
+                provider::Event::ToolCallStarted {
+                    index: 1,
+                    id: String::from("toolu_01T1x1fJ34qAmk2tNTrN7Up6"),
+                    name: String::from("get_weather"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 1,
+                    delta: String::from(r#"{"location":"#),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 1,
+                    delta: String::from(r#" "San Francisco, CA"}"#),
+                },
+                provider::Event::ToolCallDone { index: 1 },
+                provider::Event::Usage(Usage {
+                    cached_input_tokens: 0_u64.into(),
+                    input_tokens: 472_u64.into(),
+                    output_tokens: 89_u64.into(),
+                    ..Usage::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_tool_calls_cut_off_by_max_tokens() {
+        let events =
+            replay(include_str!("anthropic/fixtures/max_tokens.sse")).expect("fixture to replay");
+        assert_eq!(
+            events,
+            vec![
+                provider::Event::ToolCallStarted {
+                    index: 0,
+                    id: String::from("toolu_01D7FLrfh4GYq7yT1ULmeyML"),
+                    name: String::from("read_file"),
+                },
+                provider::Event::ToolArgsDelta {
+                    index: 0,
+                    delta: String::from(r#"{"path": "/etc/pas"#),
+                },
+                provider::Event::Incomplete(FinishReason::Length),
+                provider::Event::Usage(Usage {
+                    cached_input_tokens: 0_u64.into(),
+                    input_tokens: 410_u64.into(),
+                    output_tokens: 16_u64.into(),
+                    ..Usage::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_error_events() {
+        let error = replay(include_str!("anthropic/fixtures/error.sse"))
+            .expect_err("error event to fail the stream");
+        let_assert!(&Error::Api(ref api_error) = &error);
+        assert_eq!(api_error.kind, ApiErrorKind::Overloaded);
+        assert_eq!(api_error.code.as_deref(), Some("overloaded_error"));
+        assert_eq!(
+            error.to_string(),
+            "API error (overloaded_error): Overloaded"
+        );
+    }
+
+    #[test]
+    fn converts_thread() {
+        let messages = [
+            Message {
+                id: String::from("1"),
+                role: message::Role::Developer,
+                parts: vec![message::Part::Text(message::TextPart {
+                    text: String::from("Be brief."),
+                })],
+                model: None,
+            },
+            Message {
+                id: String::from("2"),
+                role: message::Role::User,
+                parts: vec![message::Part::Text(message::TextPart {
+                    text: String::from("Weather?"),
+                })],
+                model: None,
+            },
+            Message {
+                id: String::from("3"),
+                role: message::Role::Assistant,
+                parts: vec![
+                    message::Part::Tool(message::ToolPart {
+                        tool: message::ToolCall {
+                            id: String::from("toolu_1"),
+                            name: String::from("get_weather"),
+                            args: json!({ "location": "Paris" }),
+                            result: Some(json!({ "temperature": 20_i32 }).into()),
+                        },
+                    }),
+                    message::Part::Text(message::TextPart {
+                        text: String::from("It is 20 degrees."),
+                    }),
+                ],
+                model: None,
+            },
+            Message {
+                id: String::from("4"),
+                role: message::Role::User,
+                parts: vec![message::Part::Text(message::TextPart {
+                    text: String::from("Thanks!"),
+                })],
+                model: None,
+            },
+        ];
+
+        let thread = Thread::try_from(messages.as_slice()).expect("thread to convert");
+        assert_eq!(
+            thread,
+            Thread {
+                system: Some(String::from("Be brief.")),
+                messages: vec![
+                    InputMessage {
+                        role: Role::User,
+                        content: vec![ContentBlock::Text {
+                            text: String::from("Weather?"),
+                        }],
+                    },
+                    InputMessage {
+                        role: Role::Assistant,
+                        content: vec![ContentBlock::ToolUse {
+                            id: String::from("toolu_1"),
+                            name: String::from("get_weather"),
+                            input: json!({ "location": "Paris" }),
+                        }],
+                    },
+                    InputMessage {
+                        role: Role::User,
+                        content: vec![ContentBlock::ToolResult {
+                            tool_use_id: String::from("toolu_1"),
+                            content: ToolResultContent::Text(String::from(r#"{"temperature":20}"#)),
+                        }],
+                    },
+                    InputMessage {
+                        role: Role::Assistant,
+                        content: vec![ContentBlock::Text {
+                            text: String::from("It is 20 degrees."),
+                        }],
+                    },
+                    InputMessage {
+                        role: Role::User,
+                        content: vec![ContentBlock::Text {
+                            text: String::from("Thanks!"),
+                        }],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn converts_images_and_files() {
+        let image = message::Part::Image(message::ImagePart {
+            source: message::Source::Url {
+                url: String::from("https://example.com/cat.png"),
+            },
+        });
+        let file = message::Part::File(message::FilePart {
+            source: message::Source::Base64 {
+                media_type: String::from("application/pdf"),
+                data: String::from("JVBERi0="),
+            },
+            filename: Some(String::from("report.pdf")),
+        });
+        let message = |role, parts| Message {
+            id: String::from("1"),
+            role,
+            parts,
+            model: None,
+        };
+
+        let thread =
+            Thread::try_from([message(message::Role::User, vec![image.clone(), file])].as_slice())
+                .expect("thread to convert");
+        assert_eq!(
+            serde_json::to_value(&thread.messages).expect("messages to serialize"),
+            json!([{
+                "role": "user",
+                "content": [
+                    {
+                        "type": "image",
+                        "source": {"type": "url", "url": "https://example.com/cat.png"},
+                    },
+                    {
+                        "type": "document",
+                        "source": {
+                            "type": "base64",
+                            "media_type": "application/pdf",
+                            "data": "JVBERi0=",
+                        },
+                        "title": "report.pdf",
+                    },
+                ],
+            }])
+        );
+
+        for role in [message::Role::Developer, message::Role::Assistant] {
+            let_assert!(
+                Err(Error::InvalidMessages(_)) =
+                    Thread::try_from([message(role, vec![image.clone()])].as_slice())
+            );
+        }
+    }
+
+    #[test]
+    fn converts_tool_outputs() {
+        let tool = |result| {
+            message::Part::Tool(message::ToolPart {
+                tool: message::ToolCall {
+                    id: String::from("call"),
+                    name: String::from("screenshot"),
+                    args: json!({}),
+                    result: Some(result),
+                },
+            })
+        };
+        let screenshot = message::ToolOutput::Content {
+            content: vec![
+                message::ToolContent::Text {
+                    text: String::from("The page is blank."),
+                },
+                message::ToolContent::Image(message::ImagePart {
+                    source: message::Source::Base64 {
+                        media_type: String::from("image/png"),
+                        data: String::from("iVBORw0K"),
+                    },
+                }),
+            ],
+        };
+        let messages = [Message {
+            id: String::from("1"),
+            role: message::Role::Assistant,
+            parts: vec![tool(json!("done").into()), tool(screenshot)],
+            model: None,
+        }];
+
+        let thread = Thread::try_from(messages.as_slice()).expect("thread to convert");
+        let [_, ref results] = *thread.messages else {
+            panic!("expected tool uses and results, got {:?}", thread.messages);
+        };
+        assert_eq!(
+            serde_json::to_value(results).expect("results to serialize"),
+            json!({
+                "role": "user",
+                "content": [
+                    {"type": "tool_result", "tool_use_id": "call", "content": "\"done\""},
+                    {
+                        "type": "tool_result",
+                        "tool_use_id": "call",
+                        "content": [
+                            {"type": "text", "text": "The page is blank."},
+                            {
+                                "type": "image",
+                                "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0K"},
+                            },
+                        ],
+                    },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn streams_thinking() {
+        let events =
+            replay(include_str!("anthropic/fixtures/thinking.sse")).expect("fixture to replay");
+        assert_eq!(
+            events,
+            vec![
+                provider::Event::ReasoningStarted {
+                    index: 0,
+                    id: None,
+                    provider: String::from(PROVIDER),
+                },
+                provider::Event::ReasoningDelta {
+                    index: 0,
+                    delta: String::from("27 * 453 = 27 * 400 + 27 * 53"),
+                },
+                provider::Event::ReasoningDelta {
+                    index: 0,
+                    delta: String::from(" = 10800 + 1431 = 12231"),
+                },
+                provider::Event::ReasoningDone {
+                    index: 0,
+                    encrypted_content: Some(String::from(
+                        "EqQBCgIYAhIM1gbcDa9GJwZA2b3hGgxBdjrkzLoky3dl1pkiMOYds"
+                    )),
+                },
+                provider::Event::ReasoningStarted {
+                    index: 1,
+                    id: None,
+                    provider: String::from(PROVIDER),
+                },
+                provider::Event::ReasoningDone {
+                    index: 1,
+                    encrypted_content: Some(String::from(
+                        "EmwKAhgBEgy3va3pzix/LafPsn4aDFIT2Xlxh0L5L8rLVyIwxtE3rAFBa8cr3qpP"
+                    )),
+                },
+                provider::Event::TextDelta {
+                    index: 2,
+                    delta: String::new(),
+                },
+                provider::Event::TextDelta {
+                    index: 2,
+                    delta: String::from("27 * 453 = 12,231"),
+                },
+                provider::Event::Usage(Usage {
+                    cached_input_tokens: 0_u64.into(),
+                    input_tokens: 36_u64.into(),
+                    output_tokens: 112_u64.into(),
+                    ..Usage::default()
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn sends_thinking_back() {
+        let reasoning = |text: &str, provider: &str| {
+            message::Part::Reasoning(message::ReasoningPart {
+                text: text.to_owned(),
+                id: None,
+                encrypted_content: Some(String::from("signature")),
+                provider: Some(provider.to_owned()),
+            })
+        };
+        let messages = [Message {
+            id: String::from("1"),
+            role: message::Role::Assistant,
+            parts: vec![
+                reasoning("Look it up.", PROVIDER),
+                message::Part::Tool(message::ToolPart {
+                    tool: message::ToolCall {
+                        id: String::from("toolu_1"),
+                        name: String::from("get_weather"),
+                        args: json!({}),
+                        result: Some(json!("Sunny").into()),
+                    },
+                }),
+                reasoning("", PROVIDER),
+                // Reasoning of other providers is skipped.
+                reasoning("Answer.", "openai"),
+                message::Part::Text(message::TextPart {
+                    text: String::from("It is sunny."),
+                }),
+            ],
+            model: None,
+        }];
+
+        let thread = Thread::try_from(messages.as_slice()).expect("thread to convert");
+        assert_eq!(
+            serde_json::to_value(&thread.messages).expect("messages to serialize"),
+            json!([
+                {
+                    "role": "assistant",
+                    "content": [
+                        {"type": "thinking", "thinking": "Look it up.", "signature": "signature"},
+                        {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}},
+                    ],
+                },
+                {
+                    "role": "user",
+                    "content": [{"type": "tool_result", "tool_use_id": "toolu_1", "content": "\"Sunny\""}],
+                },
+                {
+                    "role": "assistant",
+                    "content": [
+                        {"type": "redacted_thinking", "data": "signature"},
+                        {"type": "text", "text": "It is sunny."},
+                    ],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn enables_thinking() {
+        let config = |model: Model, effort: Option<config::ReasoningEffort>| GenerateConfig {
+            model,
+            reasoning: config::Reasoning {
+                effort,
+                ..config::Reasoning::default()
+            },
+            ..GenerateConfig::default()
+        };
+        assert_eq!(
+            thinking(&config(Model::ClaudeSonnet4, None), None).expect("thinking to be supported"),
+            None
+        );
+        assert_eq!(
+            thinking(
+                &config(Model::ClaudeSonnet4, Some(config::ReasoningEffort::High)),
+                None
+            )
+            .expect("thinking to be supported"),
+            Some(Thinking::Enabled {
+                budget_tokens: HIGH_THINKING_BUDGET
+            })
+        );
+        let_assert!(
+            Err(Error::InvalidConfig(_)) = thinking(
+                &config(Model::Claude3_5Haiku, Some(config::ReasoningEffort::Low)),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn limits_thinking() {
+        let config = |max_output_tokens| GenerateConfig {
+            model: Model::ClaudeSonnet4,
+            max_output_tokens,
+            reasoning: config::Reasoning {
+                effort: Some(config::ReasoningEffort::Medium),
+                ..config::Reasoning::default()
+            },
+            ..GenerateConfig::default()
+        };
+        assert_eq!(
+            thinking(&config(Some(2_000)), None).expect("thinking to fit"),
+            Some(Thinking::Enabled {
+                budget_tokens: 1_999
+            })
+        );
+        let_assert!(Err(Error::InvalidConfig(_)) = thinking(&config(Some(1_000)), None));
+        let_assert!(
+            Err(Error::InvalidConfig(_)) = thinking(
+                &config(None),
+                Some(&ToolChoice::Any {
+                    disable_parallel_tool_use: false
+                })
+            )
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Errors returned while generating responses.
+
+use alloc::sync::Arc;
+use core::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+use bigdecimal::BigDecimal;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+
+/// An error that occurred while generating a response.
+#[derive(Debug, Clone, thiserror::Error)]
+#[non_exhaustive]
+#[expect(clippy::error_impl_error, reason = "this is the crate's error type")]
+pub enum Error {
+    /// The provider rejected the request or failed while generating.
+    #[error(transparent)]
+    Api(#[from] ApiError),
+    /// The provider could not be reached or the connection was interrupted.
+    #[error("Failed to connect: {0}")]
+    Network(#[source] Arc<reqwest::Error>),
+    /// The provider did not respond in time.
+    #[error("Timed out waiting for {0}")]
+    Timeout(String),
+    /// The session's budget does not allow another model call.
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(#[from] BudgetExceeded),
+    /// The provider sent data that could not be parsed.
+    #[error("Failed to parse event: {0}")]
+    Parse(String),
+    /// The messages cannot be sent to the provider.
+    #[error("Invalid messages: {0}")]
+    InvalidMessages(String),
+    /// The configuration cannot be used for the request, e.g. reasoning for a
+    /// model that does not reason.
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    /// The model's output does not match the requested schema.
+    #[error("Invalid output: {0}")]
+    InvalidOutput(String),
+    /// The provider sent events in an unexpected order.
+    #[error("Unexpected event: {0}")]
+    UnexpectedEvent(String),
+    /// Any other error, e.g. one that was deserialized from an error part.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl Error {
+    /// Returns whether retrying the request may succeed.
+    ///
+    /// Rate limits, overloaded or failing servers, network errors and timeouts
+    /// are transient. Everything else fails again with the same request.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        match *self {
+            Self::Api(ref error) => error.kind.is_retryable(),
+            Self::Network(_) | Self::Timeout(_) => true,
+            Self::BudgetExceeded(_)
+            | Self::Parse(_)
+            | Self::InvalidMessages(_)
+            | Self::InvalidConfig(_)
+            | Self::InvalidOutput(_)
+            | Self::UnexpectedEvent(_)
+            | Self::Other(_) => false,
+        }
+    }
+
+    /// Returns whether another model may succeed where the failing one did not.
+    ///
+    /// Besides transient errors, this includes exhausted quotas and models that
+    /// do not exist at the endpoint. Invalid requests or credentials fail with
+    /// any model.
+    #[must_use]
+    pub const fn allows_fallback(&self) -> bool {
+        match *self {
+            Self::Api(ref error) => {
+                error.kind.is_retryable()
+                    || matches!(
+                        error.kind,
+                        ApiErrorKind::QuotaExceeded | ApiErrorKind::NotFound
+                    )
+            }
+            Self::Network(_) | Self::Timeout(_) => true,
+            Self::BudgetExceeded(_)
+            | Self::Parse(_)
+            | Self::InvalidMessages(_)
+            | Self::InvalidConfig(_)
+            | Self::InvalidOutput(_)
+            | Self::UnexpectedEvent(_)
+            | Self::Other(_) => false,
+        }
+    }
+
+    /// Returns how long the provider asked to wait before retrying, if it did.
+    #[must_use]
+    pub const fn retry_after(&self) -> Option<Duration> {
+        match *self {
+            Self::Api(ref error) => error.retry_after,
+            Self::Network(_)
+            | Self::Timeout(_)
+            | Self::BudgetExceeded(_)
+            | Self::Parse(_)
+            | Self::InvalidMessages(_)
+            | Self::InvalidConfig(_)
+            | Self::InvalidOutput(_)
+            | Self::UnexpectedEvent(_)
+            | Self::Other(_) => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Network(Arc::new(error))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Parse(error.to_string())
+    }
+}
+
+/// An error that occurred while loading a [`PricingTable`](crate::PricingTable).
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum PricingError {
+    /// The file could not be read.
+    #[error("Failed to read pricing table: {0}")]
+    Io(#[from] std::io::Error),
+    /// The JSON is not a valid table.
+    #[error("Invalid pricing table: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The TOML is not a valid table.
+    #[error("Invalid pricing table: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+/// The limit of a [`Budget`](crate::Budget) that was reached.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum BudgetExceeded {
+    /// The cost of the session reached the limit.
+    #[error("spent {spent} and estimated {estimated} of {limit}")]
+    Cost {
+        /// Cost of the session so far.
+        spent: BigDecimal,
+        /// Estimated cost of the next model call.
+        estimated: BigDecimal,
+        /// Maximum cost of the session.
+        limit: BigDecimal,
+    },
+    /// The tokens of the session reached the limit.
+    #[error("used {used} and estimated {estimated} of {limit} tokens")]
+    Tokens {
+        /// Tokens of the session so far.
+        used: u64,
+        /// Estimated tokens of the next model call.
+        estimated: u64,
+        /// Maximum number of tokens of the session.
+        limit: u64,
+    },
+}
+
+/// An error reported by a provider's API.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct ApiError {
+    /// Category of the error.
+    pub kind: ApiErrorKind,
+    /// HTTP status of the response. `None` if the error occurred mid-stream.
+    pub status: Option<u16>,
+    /// The provider's error code, e.g. `rate_limit_exceeded`.
+    pub code: Option<String>,
+    /// The provider's description of the error.
+    pub message: String,
+    /// How long the provider asked to wait before retrying.
+    pub retry_after: Option<Duration>,
+}
+
+impl ApiError {
+    /// Creates an error, classifying it by its status, code and message.
+    #[must_use]
+    pub fn new(status: Option<u16>, code: Option<String>, message: String) -> Self {
+        Self {
+            kind: ApiErrorKind::classify(status, code.as_deref(), &message),
+            status,
+            code,
+            message,
+            retry_after: None,
+        }
+    }
+
+    /// Creates an error from an unsuccessful HTTP response.
+    pub(crate) async fn from_response(response: reqwest::Response) -> Self {
+        /// The error body returned by OpenAI- and Anthropic-compatible APIs.
+        #[derive(Deserialize)]
+        struct Body {
+            error: BodyError,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum BodyError {
+            Object {
+                #[serde(rename = "type")]
+                kind: Option<String>,
+                code: Option<serde_json::Value>,
+                message: String,
+            },
+            Message(String),
+        }
+
+        let status = response.status();
+        let retry_after = retry_after(response.headers());
+        let text = response.text().await.unwrap_or_default();
+        let (code, message) = match serde_json::from_str::<Body>(&text) {
+            Ok(Body {
+                error:
+                    BodyError::Object {
+                        kind,
+                        code,
+                        message,
+                    },
+            }) => (
+                code.and_then(|code| match code {
+                    serde_json::Value::String(code) => Some(code),
+                    serde_json::Value::Null => None,
+                    code @ (serde_json::Value::Bool(_)
+                    | serde_json::Value::Number(_)
+                    | serde_json::Value::Array(_)
+                    | serde_json::Value::Object(_)) => Some(code.to_string()),
+                })
+                .or(kind),
+                message,
+            ),
+            Ok(Body {
+                error: BodyError::Message(message),
+            }) => (None, message),
+            Err(_) if text.trim().is_empty() => (
+                None,
+                status.canonical_reason().unwrap_or_default().to_owned(),
+            ),
+            Err(_) => (None, text),
+        };
+
+        Self {
+            retry_after,
+            ..Self::new(Some(status.as_u16()), code, message)
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("API error")?;
+        match (self.status, self.code.as_deref()) {
+            (Some(status), Some(code)) => write!(formatter, " ({status}, {code})")?,
+            (Some(status), None) => write!(formatter, " ({status})")?,
+            (None, Some(code)) => write!(formatter, " ({code})")?,
+            (None, None) => {}
+        }
+        write!(formatter, ": {}", self.message)
+    }
+}
+
+/// Category of an [`ApiError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ApiErrorKind {
+    /// Too many requests were sent.
+    RateLimited,
+    /// The account ran out of credits.
+    QuotaExceeded,
+    /// The API key is missing or invalid.
+    Authentication,
+    /// The API key may not use the requested resource.
+    PermissionDenied,
+    /// The model or endpoint does not exist.
+    NotFound,
+    /// The messages do not fit into the model's context window.
+    ContextLengthExceeded,
+    /// The JSON schema of a tool was rejected.
+    InvalidToolSchema,
+    /// The request was rejected for any other reason.
+    InvalidRequest,
+    /// The provider is temporarily overloaded.
+    Overloaded,
+    /// The provider failed to process the request.
+    Server,
+    /// The error could not be classified.
+    Other,
+}
+
+impl ApiErrorKind {
+    /// Returns whether errors of this kind are transient.
+    #[must_use]
+    pub const fn is_retryable(self) -> bool {
+        matches!(self, Self::RateLimited | Self::Overloaded | Self::Server)
+    }
+
+    /// Classifies an error by its HTTP status, provider error code and message.
+    fn classify(status: Option<u16>, code: Option<&str>, message: &str) -> Self {
+        let message = message.to_lowercase();
+        let code = code.unwrap_or_default();
+        if code == "context_length_exceeded"
+            || message.contains("context length")
+            || message.contains("context window")
+            || message.contains("prompt is too long")
+        {
+            return Self::ContextLengthExceeded;
+        }
+        if code == "invalid_function_parameters" || message.contains("invalid schema for function")
+        {
+            return Self::InvalidToolSchema;
+        }
+        match (status, code) {
+            (_, "insufficient_quota") => Self::QuotaExceeded,
+            (Some(429), _) | (_, "rate_limit_exceeded" | "rate_limit_error") => Self::RateLimited,
+            (Some(401), _) | (_, "invalid_api_key" | "authentication_error") => {
+                Self::Authentication
+            }
+            (Some(403), _) | (_, "permission_error") => Self::PermissionDenied,
+            (Some(404), _) | (_, "not_found_error" | "model_not_found") => Self::NotFound,
+            (Some(529), _) | (_, "overloaded_error") => Self::Overloaded,
+            (Some(500..=599), _) | (_, "api_error" | "server_error") => Self::Server,
+            (Some(400..=499), _) | (_, "invalid_request_error") => Self::InvalidRequest,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Returns the delay requested by the `retry-after-ms` or `retry-after` headers.
+///
+/// HTTP dates are not supported.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+    };
+    header("retry-after-ms")
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_millis)
+        .or_else(|| {
+            let seconds = header("retry-after")?.parse().ok()?;
+            Duration::try_from_secs_f64(seconds).ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let cases = [
+            (
+                Some(429_u16),
+                Some("rate_limit_exceeded"),
+                "Slow down",
+                ApiErrorKind::RateLimited,
+            ),
+            (
+                Some(429_u16),
+                Some("insufficient_quota"),
+                "Pay up",
+                ApiErrorKind::QuotaExceeded,
+            ),
+            (
+                Some(401_u16),
+                None,
+                "Unauthorized",
+                ApiErrorKind::Authentication,
+            ),
+            (
+                Some(400_u16),
+                Some("context_length_exceeded"),
+                "Too long",
+                ApiErrorKind::ContextLengthExceeded,
+            ),
+            (
+                Some(400_u16),
+                Some("invalid_request_error"),
+                "prompt is too long: 210000 tokens > 200000 maximum",
+                ApiErrorKind::ContextLengthExceeded,
+            ),
+            (
+                Some(400_u16),
+                Some("invalid_function_parameters"),
+                "Invalid schema for function 'add'",
+                ApiErrorKind::InvalidToolSchema,
+            ),
+            (
+                None,
+                Some("overloaded_error"),
+                "Overloaded",
+                ApiErrorKind::Overloaded,
+            ),
+            (Some(502_u16), None, "Bad Gateway", ApiErrorKind::Server),
+            (None, None, "Unknown", ApiErrorKind::Other),
+        ];
+        for (status, code, message, kind) in cases {
+            assert_eq!(
+                ApiError::new(status, code.map(ToOwned::to_owned), message.to_owned()).kind,
+                kind,
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", "2".parse().expect("header to parse"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+        headers.insert("retry-after-ms", "1500".parse().expect("header to parse"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,422 @@
+//! Fine-grained events emitted while generating an assistant message.
+//!
+//! Instead of snapshots of the entire message, [`provider_events`](crate::provider_events)
+//! yields an [`Event`] for every change. The assistant [`Message`] can be
+//! reconstructed from these events with an [`Accumulator`], which makes it
+//! cheap to render incrementally or forward over the network.
+
+use alloc::collections::BTreeMap;
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    Error, Message, Model, Usage,
+    message::{self, Part, ReasoningPart, TextPart, ToolCall, ToolOutput, ToolPart},
+    util::parse_incomplete_json,
+};
+
+/// A change to the assistant message being generated.
+///
+/// `part_index` refers to the index of the part in [`Message::parts`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Event {
+    /// A new assistant message was started.
+    MessageStarted {
+        /// Unique identifier for the message.
+        id: String,
+        /// The model that generates the message.
+        model: Model,
+    },
+    /// Text was appended to the text part at `part_index`, starting it if needed.
+    TextDelta {
+        /// Index of the text part.
+        part_index: usize,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// A tool call was started at `part_index`.
+    ToolCallStarted {
+        /// Index of the tool part.
+        part_index: usize,
+        /// Unique identifier for the tool call.
+        id: String,
+        /// Name of the tool being called.
+        name: String,
+    },
+    /// Arguments were appended to the tool call at `part_index`.
+    ToolArgsDelta {
+        /// Index of the tool part.
+        part_index: usize,
+        /// The raw JSON fragment that was appended.
+        delta: String,
+    },
+    /// The tool call at `part_index` produced a result.
+    ToolResult {
+        /// Index of the tool part.
+        part_index: usize,
+        /// The result of the tool call.
+        result: ToolOutput,
+    },
+    /// Reasoning was started at `part_index`.
+    ReasoningStarted {
+        /// Index of the reasoning part.
+        part_index: usize,
+        /// The provider's identifier of the reasoning.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        /// Name of the provider that produced the reasoning.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider: Option<String>,
+    },
+    /// Summary text was appended to the reasoning part at `part_index`.
+    ReasoningDelta {
+        /// Index of the reasoning part.
+        part_index: usize,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// The reasoning at `part_index` is complete.
+    ReasoningDone {
+        /// Index of the reasoning part.
+        part_index: usize,
+        /// The encrypted reasoning, if the provider returned it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encrypted_content: Option<String>,
+    },
+    /// A step, i.e. a model call and the execution of the tools it called,
+    /// was started.
+    StepStarted,
+    /// The step was completed, including the results of its tool calls.
+    StepFinished,
+    /// Token usage of a single model call.
+    Usage(Usage),
+    /// A model call failed before producing any output and is retried after
+    /// `delay`.
+    Retry {
+        /// The attempt that failed, starting at `1`.
+        attempt: u32,
+        /// How long the loop waits before the next attempt.
+        delay: Duration,
+        /// Description of the error.
+        error: String,
+    },
+    /// The model failed before producing any output and the generation
+    /// continues with the next fallback model.
+    Fallback {
+        /// The model that generates the rest of the message.
+        model: Model,
+        /// Description of the error.
+        error: String,
+    },
+    /// The generation finished.
+    Finished {
+        /// Why the generation finished.
+        reason: FinishReason,
+    },
+}
+
+/// The reason a generation finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum FinishReason {
+    /// The model finished its response.
+    Stop,
+    /// The model called client tools whose results must be set by the caller.
+    ToolCalls,
+    /// The generation reached [`GenerateConfig::max_steps`](crate::GenerateConfig::max_steps).
+    MaxSteps,
+    /// A [stop condition](crate::config::StopCondition) was met.
+    StopCondition,
+    /// The generation was cancelled. Pending tool calls were resolved with an
+    /// error result.
+    Cancelled,
+    /// The response reached
+    /// [`GenerateConfig::max_output_tokens`](crate::GenerateConfig::max_output_tokens).
+    /// Pending tool calls were resolved with an error result.
+    Length,
+    /// The provider stopped the response because of its content filter.
+    /// Pending tool calls were resolved with an error result.
+    ContentFilter,
+}
+
+/// Reconstructs a [`Message`] from [`Event`]s.
+#[derive(Debug, Clone, Default)]
+pub struct Accumulator {
+    tool_args: BTreeMap<usize, String>,
+}
+
+impl Accumulator {
+    /// Applies the event to the message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the event refers to a part that does not exist or
+    /// has a different kind.
+    pub fn apply(&mut self, message: &mut Message, event: &Event) -> Result<(), Error> {
+        match *event {
+            Event::MessageStarted { ref id, ref model } => {
+                *message = Message {
+                    id: id.clone(),
+                    role: message::Role::Assistant,
+                    parts: Vec::new(),
+                    model: Some(model.clone()),
+                };
+                self.tool_args.clear();
+            }
+            Event::Fallback { ref model, .. } => {
+                message.model = Some(model.clone());
+            }
+            Event::TextDelta {
+                part_index,
+                ref delta,
+            } => {
+                if part_index == message.parts.len() {
+                    message.parts.push(Part::Text(TextPart {
+                        text: delta.clone(),
+                    }));
+                } else {
+                    let Some(&mut Part::Text(ref mut text_part)) =
+                        message.parts.get_mut(part_index)
+                    else {
+                        return Err(Error::UnexpectedEvent(format!(
+                            "No text part at index {part_index}"
+                        )));
+                    };
+                    text_part.text.push_str(delta);
+                }
+            }
+            Event::ToolCallStarted {
+                part_index,
+                ref id,
+                ref name,
+            } => {
+                if part_index != message.parts.len() {
+                    return Err(Error::UnexpectedEvent(format!(
+                        "Tool call started at index {part_index} instead of {}",
+                        message.parts.len()
+                    )));
+                }
+                self.tool_args.insert(part_index, String::new());
+                message.parts.push(Part::Tool(ToolPart {
+                    tool: ToolCall {
+                        id: id.clone(),
+                        name: name.clone(),
+                        args: Value::Null,
+                        result: None,
+                    },
+                }));
+            }
+            Event::ToolArgsDelta {
+                part_index,
+                ref delta,
+            } => {
+                let tool = tool_call(message, part_index)?;
+                let args = self.tool_args.entry(part_index).or_default();
+                args.push_str(delta);
+                tool.args = parse_incomplete_json(args).unwrap_or_default();
+            }
+            Event::ToolResult {
+                part_index,
+                ref result,
+            } => {
+                tool_call(message, part_index)?.result = Some(result.clone());
+            }
+            Event::ReasoningStarted {
+                part_index,
+                ref id,
+                ref provider,
+            } => {
+                if part_index != message.parts.len() {
+                    return Err(Error::UnexpectedEvent(format!(
+                        "Reasoning started at index {part_index} instead of {}",
+                        message.parts.len()
+                    )));
+                }
+                message.parts.push(Part::Reasoning(ReasoningPart {
+                    id: id.clone(),
+                    provider: provider.clone(),
+                    ..ReasoningPart::default()
+                }));
+            }
+            Event::ReasoningDelta {
+                part_index,
+                ref delta,
+            } => {
+                reasoning(message, part_index)?.text.push_str(delta);
+            }
+            Event::ReasoningDone {
+                part_index,
+                ref encrypted_content,
+            } => {
+                reasoning(message, part_index)?
+                    .encrypted_content
+                    .clone_from(encrypted_content);
+            }
+            Event::StepStarted
+            | Event::StepFinished
+            | Event::Usage(_)
+            | Event::Retry { .. }
+            | Event::Finished { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// Returns the reasoning at the given part index.
+fn reasoning(message: &mut Message, part_index: usize) -> Result<&mut ReasoningPart, Error> {
+    let Some(&mut Part::Reasoning(ref mut reasoning)) = message.parts.get_mut(part_index) else {
+        return Err(Error::UnexpectedEvent(format!(
+            "No reasoning part at index {part_index}"
+        )));
+    };
+    Ok(reasoning)
+}
+
+/// Returns the tool call at the given part index.
+fn tool_call(message: &mut Message, part_index: usize) -> Result<&mut ToolCall, Error> {
+    let Some(&mut Part::Tool(ToolPart { ref mut tool })) = message.parts.get_mut(part_index) else {
+        return Err(Error::UnexpectedEvent(format!(
+            "No tool part at index {part_index}"
+        )));
+    };
+    Ok(tool)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn reconstructs_messages() {
+        let events = [
+            Event::MessageStarted {
+                id: String::from("message"),
+                model: Model::Gpt4_1,
+            },
+            Event::TextDelta {
+                part_index: 0,
+                delta: String::from("Let me "),
+            },
+            Event::TextDelta {
+                part_index: 0,
+                delta: String::from("check."),
+            },
+            Event::ToolCallStarted {
+                part_index: 1,
+                id: String::from("call"),
+                name: String::from("weather"),
+            },
+            Event::ToolArgsDelta {
+                part_index: 1,
+                delta: String::from(r#"{"city": "Par"#),
+            },
+            Event::ToolArgsDelta {
+                part_index: 1,
+                delta: String::from(r#"is"}"#),
+            },
+            Event::ToolResult {
+                part_index: 1,
+                result: json!("sunny").into(),
+            },
+            Event::Finished {
+                reason: FinishReason::Stop,
+            },
+        ];
+
+        let mut message = Message {
+            id: String::new(),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        };
+        let mut accumulator = Accumulator::default();
+        for event in &events {
+            accumulator
+                .apply(&mut message, event)
+                .expect("event to apply");
+        }
+
+        assert_eq!(
+            message,
+            Message {
+                id: String::from("message"),
+                role: message::Role::Assistant,
+                parts: vec![
+                    Part::Text(TextPart {
+                        text: String::from("Let me check."),
+                    }),
+                    Part::Tool(ToolPart {
+                        tool: ToolCall {
+                            id: String::from("call"),
+                            name: String::from("weather"),
+                            args: json!({ "city": "Paris" }),
+                            result: Some(json!("sunny").into()),
+                        },
+                    }),
+                ],
+                model: Some(Model::Gpt4_1),
+            }
+        );
+
+        assert!(
+            accumulator
+                .apply(
+                    &mut message,
+                    &Event::ToolArgsDelta {
+                        part_index: 0,
+                        delta: String::from("{}"),
+                    },
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reconstructs_reasoning() {
+        let events = [
+            Event::ReasoningStarted {
+                part_index: 0,
+                id: Some(String::from("rs_1")),
+                provider: Some(String::from("openai")),
+            },
+            Event::ReasoningDelta {
+                part_index: 0,
+                delta: String::from("Adding."),
+            },
+            Event::ReasoningDone {
+                part_index: 0,
+                encrypted_content: Some(String::from("secret")),
+            },
+        ];
+
+        let mut message = Message {
+            id: String::from("message"),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        };
+        let mut accumulator = Accumulator::default();
+        for event in &events {
+            accumulator
+                .apply(&mut message, event)
+                .expect("event to apply");
+        }
+
+        assert_eq!(
+            message.parts,
+            [Part::Reasoning(ReasoningPart {
+                text: String::from("Adding."),
+                id: Some(String::from("rs_1")),
+                encrypted_content: Some(String::from("secret")),
+                provider: Some(String::from("openai")),
+            })]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{Error, Model};
+
 /// Represents a message exchanged in the AI conversation, including its role and content parts.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
@@ -7,8 +9,11 @@ pub struct Message {
     pub id: String,
     /// The role of the message sender (developer, user, assistant).
     pub role: Role,
-    /// The content parts of the message (text, tool, or error).
+    /// The content parts of the message (text, image, file, tool, reasoning or error).
     pub parts: Vec<Part>,
+    /// The model that generated the message. Only set for assistant messages.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
 }
 
 impl Message {
@@ -37,13 +42,20 @@ pub enum Role {
     Assistant,
 }
 
-/// A part of a message, which can be text, a tool call, or an error.
+/// A part of a message, which can be text, an image, a file, a tool call,
+/// reasoning or an error.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Part {
     /// Text content part.
     Text(TextPart),
+    /// Image part. Only occurs in user messages.
+    Image(ImagePart),
+    /// File part, e.g. a PDF. Only occurs in user messages.
+    File(FilePart),
     /// Tool call part.
     Tool(ToolPart),
+    /// Reasoning part of reasoning models.
+    Reasoning(ReasoningPart),
     /// Error part. Only occurs when the stream fails.
     Error(ErrorPart),
 }
@@ -55,6 +67,55 @@ pub struct TextPart {
     pub text: String,
 }
 
+/// An image for vision-capable models.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImagePart {
+    /// Where the image is loaded from.
+    pub source: Source,
+}
+
+/// A file, such as a PDF, for models that can read files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FilePart {
+    /// Where the file is loaded from.
+    pub source: Source,
+    /// Name of the file, e.g. `report.pdf`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+}
+
+/// The content of an image or file part.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    /// Content that the provider downloads from a URL.
+    Url {
+        /// The URL of the content.
+        url: String,
+    },
+    /// Content sent along with the message.
+    Base64 {
+        /// Media type of the content, e.g. `image/png` or `application/pdf`.
+        media_type: String,
+        /// The base64-encoded content.
+        data: String,
+    },
+}
+
+impl Source {
+    /// Returns the URL of the content, which is a data URL for base64 content.
+    #[must_use]
+    pub fn to_url(&self) -> String {
+        match *self {
+            Self::Url { ref url } => url.clone(),
+            Self::Base64 {
+                ref media_type,
+                ref data,
+            } => format!("data:{media_type};base64,{data}"),
+        }
+    }
+}
+
 /// Tool call content for a message part.
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ToolPart {
@@ -62,6 +123,31 @@ pub struct ToolPart {
     pub tool: ToolCall,
 }
 
+/// The reasoning of a model that precedes the rest of its response.
+///
+/// Reasoning parts are sent back to the provider that produced them, so the
+/// model can build on its reasoning in later turns. Other providers skip them.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReasoningPart {
+    /// Summary of the reasoning, or the thinking of Claude models. Empty
+    /// unless summaries were requested with
+    /// [`Reasoning::summary`](crate::config::Reasoning::summary) or the
+    /// thinking was redacted.
+    pub text: String,
+    /// The provider's identifier of the reasoning.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The encrypted reasoning, which lets the provider continue from it
+    /// without storing responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_content: Option<String>,
+    /// Name of the provider that produced the reasoning, e.g. `"openai"` or
+    /// `"anthropic"`. Reasoning without a provider was produced by the OpenAI
+    /// Responses API before the provider was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+}
+
 /// Represents a tool call, including its arguments and result.
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ToolCall {
@@ -72,24 +158,147 @@ pub struct ToolCall {
     /// Arguments for the tool call.
     pub args: serde_json::Value,
     /// Optional result of the tool call.
-    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub result: Option<ToolOutput>,
 }
 
-/// Represents an error part in a message, wrapping an error value.
-#[derive(Debug)]
-pub struct ErrorPart {
-    /// The error value.
-    pub error: anyhow::Error,
+/// The result of a tool call.
+///
+/// Executors set with [`ToolBuilder::executor`](crate::tool::ToolBuilder::executor)
+/// produce JSON. Tools return content, e.g. a screenshot along with a
+/// description, with
+/// [`ToolBuilder::output_executor`](crate::tool::ToolBuilder::output_executor).
+/// Providers send content as multi-part tool results where the API supports
+/// it. Otherwise, the text is sent as the tool result and the images and files
+/// follow in a user message.
+///
+/// JSON is serialized as is. Content is serialized as
+/// `{"type": "content", "content": [...]}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolOutput {
+    /// A JSON value, which is sent as its serialization.
+    Json {
+        /// The JSON value.
+        value: serde_json::Value,
+    },
+    /// Text, images and files for the model.
+    Content {
+        /// The parts of the output.
+        content: Vec<ToolContent>,
+    },
+}
+
+/// The serialized form of [`ToolOutput::Content`].
+#[derive(Serialize)]
+#[serde(tag = "type", rename = "content")]
+struct TaggedContent<'content> {
+    content: &'content [ToolContent],
+}
+
+impl Serialize for ToolOutput {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Self::Json { ref value } => value.serialize(serializer),
+            Self::Content { ref content } => TaggedContent { content }.serialize(serializer),
+        }
+    }
 }
 
-impl Clone for ErrorPart {
-    fn clone(&self) -> Self {
-        Self {
-            error: anyhow::Error::from_boxed(self.error.to_string().into()),
+impl<'de> Deserialize<'de> for ToolOutput {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        if let Some(object) = value.as_object()
+            && object.len() == 2
+            && object.get("type").and_then(serde_json::Value::as_str) == Some("content")
+            && let Some(content) = object.get("content")
+            && let Ok(content) = serde_json::from_value(content.clone())
+        {
+            return Ok(Self::Content { content });
         }
+        Ok(Self::Json { value })
     }
 }
 
+impl ToolOutput {
+    /// Creates an output of a single text part.
+    #[must_use]
+    pub fn text<T: Into<String>>(text: T) -> Self {
+        Self::Content {
+            content: vec![ToolContent::Text { text: text.into() }],
+        }
+    }
+
+    /// Returns the text of the output: the serialized JSON, or the text parts
+    /// separated by newlines.
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        match *self {
+            Self::Json { ref value } => value.to_string(),
+            Self::Content { ref content } => content
+                .iter()
+                .filter_map(|content| match *content {
+                    ToolContent::Text { ref text } => Some(text.as_str()),
+                    ToolContent::Image(_) | ToolContent::File(_) => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+
+    /// Returns the parts of the output. JSON becomes a single text part.
+    #[must_use]
+    pub fn into_content(self) -> Vec<ToolContent> {
+        match self {
+            Self::Json { value } => vec![ToolContent::Text {
+                text: value.to_string(),
+            }],
+            Self::Content { content } => content,
+        }
+    }
+
+    /// Returns whether the output has no images or files.
+    #[must_use]
+    pub fn is_text(&self) -> bool {
+        match *self {
+            Self::Json { .. } => true,
+            Self::Content { ref content } => content
+                .iter()
+                .all(|content| matches!(*content, ToolContent::Text { .. })),
+        }
+    }
+}
+
+impl From<serde_json::Value> for ToolOutput {
+    fn from(value: serde_json::Value) -> Self {
+        Self::Json { value }
+    }
+}
+
+/// A part of a [`ToolOutput`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolContent {
+    /// Text for the model.
+    Text {
+        /// The text content.
+        text: String,
+    },
+    /// An image, e.g. a screenshot.
+    Image(ImagePart),
+    /// A file, e.g. a generated PDF.
+    File(FilePart),
+}
+
+/// Represents an error part in a message, wrapping an error value.
+///
+/// Error parts are serialized as their message, so deserialized errors are
+/// always [`Error::Other`].
+#[derive(Debug, Clone)]
+pub struct ErrorPart {
+    /// The error value.
+    pub error: Error,
+}
+
 impl PartialEq for ErrorPart {
     fn eq(&self, other: &Self) -> bool {
         self.error.to_string() == other.error.to_string()
@@ -113,7 +322,71 @@ impl<'de> Deserialize<'de> for ErrorPart {
         D: serde::Deserializer<'de>,
     {
         Ok(Self {
-            error: anyhow::Error::from_boxed(String::deserialize(deserializer)?.into()),
+            error: Error::Other(String::deserialize(deserializer)?),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn result(call: serde_json::Value) -> Option<ToolOutput> {
+        serde_json::from_value::<ToolCall>(call)
+            .expect("tool call to deserialize")
+            .result
+    }
+
+    #[test]
+    fn serializes_json_results_as_is() {
+        let call = |result| json!({"id": "call", "name": "search", "args": {}, "result": result});
+        for value in [
+            json!(3_i32),
+            json!({"type": "json", "value": 3_i32}),
+            json!({"content": [{"type": "text", "text": "x"}]}),
+        ] {
+            let output = ToolOutput::from(value.clone());
+            assert_eq!(serde_json::to_value(&output).ok(), Some(value.clone()));
+            assert_eq!(result(call(value)), Some(output));
+        }
+        assert_eq!(result(call(json!(null))), None);
+
+        assert_eq!(
+            serde_json::to_value(ToolOutput::text("x")).ok(),
+            Some(json!({"type": "content", "content": [{"type": "text", "text": "x"}]}))
+        );
+    }
+
+    #[test]
+    fn round_trips_results() {
+        for output in [
+            ToolOutput::from(json!({"content": [{"type": "text", "text": "x"}]})),
+            ToolOutput::Content {
+                content: vec![
+                    ToolContent::Text {
+                        text: String::from("The page is blank."),
+                    },
+                    ToolContent::Image(ImagePart {
+                        source: Source::Url {
+                            url: String::from("https://example.com/screenshot.png"),
+                        },
+                    }),
+                ],
+            },
+        ] {
+            let call = ToolCall {
+                id: String::from("call"),
+                name: String::from("screenshot"),
+                args: json!({}),
+                result: Some(output),
+            };
+            let value = serde_json::to_value(&call).expect("tool call to serialize");
+            assert_eq!(
+                serde_json::from_value::<ToolCall>(value).expect("tool call to deserialize"),
+                call
+            );
+        }
+    }
+}
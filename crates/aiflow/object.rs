@@ -0,0 +1,499 @@
+//! Structured outputs, i.e. typed objects that the model writes as JSON.
+
+use core::pin::pin;
+
+use futures::{Stream, StreamExt as _, future, stream};
+use genawaiter::sync::Gen;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    Error, Event, FinishReason, GenerateConfig, Message, Provider, Session, config, event, message,
+    provider_events, tool,
+    util::{json_schema, parse_incomplete_json},
+};
+
+/// An update of an object generated by [`generate_object`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Update<T> {
+    /// The object written so far, repaired into valid JSON. Fields may be
+    /// missing and the last value may be incomplete.
+    Partial(Value),
+    /// The complete object.
+    Complete(T),
+}
+
+/// A typed object yielded by [`partial_objects`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partial<T> {
+    /// The object written so far. Until it is complete, missing fields hold
+    /// empty values.
+    pub object: T,
+    /// Whether the model finished the object.
+    pub complete: bool,
+}
+
+/// Generates an object of type `T` with the given provider, executing tools
+/// until the model stops calling them.
+///
+/// The schema of `T` is sent as the
+/// [response format](GenerateConfig::response_format). The stream yields the
+/// object whenever it changes while the model writes it and ends with the
+/// complete object. A code fence or prose around the object, which models
+/// without structured outputs tend to add, is ignored. If the model's final
+/// text does not deserialize into `T`, the stream ends with
+/// [`Error::InvalidOutput`]. `T` must be an object; see
+/// [`ResponseFormat::json_schema`](config::ResponseFormat::json_schema).
+///
+/// # Arguments
+///
+/// * `provider` - The model backend to use.
+/// * `session` - Mutable reference to the session state.
+/// * `messages` - Vector of input messages to process.
+/// * `tools` - Set of tools available for the AI to use.
+/// * `config` - Optional configuration for message generation.
+/// * `cancellation` - Token that stops the generation when cancelled.
+///
+/// # Returns
+///
+/// A stream of `Result<Update<T>>` items ending with [`Update::Complete`].
+pub fn generate_object<T, P>(
+    provider: P,
+    session: &mut Session,
+    messages: &[Message],
+    tools: tool::Set,
+    config: Option<GenerateConfig>,
+    cancellation: CancellationToken,
+) -> impl Stream<Item = Result<Update<T>, Error>>
+where
+    T: JsonSchema + DeserializeOwned,
+    P: Provider,
+{
+    let response_format = match config::ResponseFormat::json_schema::<T>() {
+        Ok(response_format) => response_format,
+        Err(error) => return stream::once(future::ready(Err(error))).left_stream(),
+    };
+    let config = GenerateConfig {
+        response_format: Some(response_format),
+        ..config.unwrap_or_default()
+    };
+    updates(provider_events(
+        provider,
+        session,
+        messages,
+        tools,
+        Some(config),
+        cancellation,
+    ))
+    .right_stream()
+}
+
+/// Deserializes the updates of [`generate_object`] into `T`, e.g. to render
+/// structured results as they arrive.
+///
+/// Before a partial object is deserialized, the required fields that the model
+/// has not written yet are filled with empty values according to the schema
+/// of `T`: `null` for nullable fields, and empty strings, zeros, `false`,
+/// empty arrays and objects otherwise. Partial objects that still do not
+/// deserialize, e.g. because an enum value is incomplete, are skipped. The
+/// stream ends with the complete object.
+pub fn partial_objects<T, S>(updates: S) -> impl Stream<Item = Result<Partial<T>, Error>>
+where
+    T: JsonSchema + DeserializeOwned,
+    S: Stream<Item = Result<Update<T>, Error>>,
+{
+    let schema = json_schema::<T>().to_value();
+    updates.filter_map(move |result| {
+        let partial = match result {
+            Ok(Update::Partial(mut value)) => {
+                let defs = schema.get("$defs").unwrap_or(&Value::Null);
+                fill(&mut value, &schema, defs);
+                serde_json::from_value(value).ok().map(|object| {
+                    Ok(Partial {
+                        object,
+                        complete: false,
+                    })
+                })
+            }
+            Ok(Update::Complete(object)) => Some(Ok(Partial {
+                object,
+                complete: true,
+            })),
+            Err(error) => Some(Err(error)),
+        };
+        future::ready(partial)
+    })
+}
+
+/// Fills the required fields that are missing from a partial object with
+/// empty values.
+fn fill(value: &mut Value, schema: &Value, defs: &Value) {
+    let schema = resolve(schema, defs);
+    match *value {
+        Value::Object(ref mut object) => {
+            let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+                return;
+            };
+            let required = schema
+                .get("required")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            for (name, property) in properties {
+                if let Some(field) = object.get_mut(name) {
+                    fill(field, property, defs);
+                } else if required.iter().any(|required| required == name)
+                    && let Some(empty) = empty(property, defs)
+                {
+                    object.insert(name.clone(), empty);
+                }
+            }
+        }
+        Value::Array(ref mut items) => {
+            if let Some(item) = schema.get("items") {
+                for value in items {
+                    fill(value, item, defs);
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {}
+    }
+}
+
+/// Returns the empty value of a schema, if it has one.
+fn empty(schema: &Value, defs: &Value) -> Option<Value> {
+    let schema = resolve(schema, defs);
+    let nullable_variant = ["anyOf", "oneOf"]
+        .iter()
+        .filter_map(|keyword| schema.get(keyword).and_then(Value::as_array))
+        .flatten()
+        .any(|variant| resolve(variant, defs).get("type") == Some(&json!("null")));
+    let kinds = match schema.get("type") {
+        Some(&Value::String(ref kind)) => vec![kind.as_str()],
+        Some(&Value::Array(ref kinds)) => kinds.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    if nullable_variant || kinds.contains(&"null") {
+        return Some(Value::Null);
+    }
+    match *kinds.first()? {
+        "string" => Some(json!("")),
+        "integer" | "number" => Some(json!(0_i32)),
+        "boolean" => Some(json!(false)),
+        "array" => Some(json!([])),
+        "object" => {
+            let mut object = json!({});
+            fill(&mut object, schema, defs);
+            Some(object)
+        }
+        _ => None,
+    }
+}
+
+/// Resolves a reference to a definition of the schema.
+fn resolve<'schema>(schema: &'schema Value, defs: &'schema Value) -> &'schema Value {
+    schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|reference| reference.strip_prefix("#/$defs/"))
+        .and_then(|name| defs.get(name))
+        .unwrap_or(schema)
+}
+
+/// Decodes the object that the model writes from the events of a generation.
+fn updates<T: DeserializeOwned>(
+    events: impl Stream<Item = Result<Event, Error>>,
+) -> impl Stream<Item = Result<Update<T>, Error>> {
+    Gen::new(|co| async move {
+        let mut events = pin!(events);
+        let mut message = Message {
+            id: String::new(),
+            role: message::Role::Assistant,
+            parts: Vec::new(),
+            model: None,
+        };
+        let mut accumulator = event::Accumulator::default();
+        let mut partial = None;
+
+        while let Some(result) = events.next().await {
+            let event = match result {
+                Ok(event) => event,
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+            if let Err(error) = accumulator.apply(&mut message, &event) {
+                co.yield_(Err(error)).await;
+                return;
+            }
+
+            if let Event::TextDelta { part_index, .. } = event {
+                let Some(&message::Part::Text(ref text_part)) = message.parts.get(part_index)
+                else {
+                    continue;
+                };
+                // Text that cannot be repaired yet is skipped.
+                let Ok(value) = parse_incomplete_json(json_text(&text_part.text)) else {
+                    continue;
+                };
+                if partial.as_ref() != Some(&value) {
+                    partial = Some(value.clone());
+                    co.yield_(Ok(Update::Partial(value))).await;
+                }
+            } else if let Event::Finished { reason } = event {
+                co.yield_(output(&message, reason).map(Update::Complete))
+                    .await;
+                return;
+            }
+        }
+    })
+}
+
+/// Deserializes the last text part of a finished message.
+fn output<T: DeserializeOwned>(message: &Message, reason: FinishReason) -> Result<T, Error> {
+    if reason != FinishReason::Stop {
+        return Err(Error::InvalidOutput(format!(
+            "The generation finished with {reason:?} before the output was written"
+        )));
+    }
+    let text = message
+        .parts
+        .iter()
+        .rev()
+        .find_map(|part| match *part {
+            message::Part::Text(ref text_part) => Some(&text_part.text),
+            message::Part::Image(_)
+            | message::Part::File(_)
+            | message::Part::Tool(_)
+            | message::Part::Reasoning(_)
+            | message::Part::Error(_) => None,
+        })
+        .ok_or_else(|| Error::InvalidOutput(String::from("The model did not write an output")))?;
+    // Text after the object is ignored.
+    serde_json::Deserializer::from_str(json_text(text))
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::InvalidOutput(String::from("The model did not write an object")))?
+        .map_err(|error| Error::InvalidOutput(error.to_string()))
+}
+
+/// Returns the JSON in the text of a model without the code fence or prose
+/// around it, which models without structured outputs tend to add.
+fn json_text(text: &str) -> &str {
+    let trimmed = text.trim_start();
+    if trimmed.starts_with('{') {
+        return trimmed;
+    }
+    let text = text.split_once("```").map_or(text, |(_, fenced)| {
+        // The info string, e.g. `json`, ends with the first line.
+        let fenced = fenced.split_once('\n').map_or("", |(_, fenced)| fenced);
+        fenced.split_once("```").map_or(fenced, |(json, _)| json)
+    });
+    text.find('{')
+        .and_then(|start| text.get(start..))
+        .unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert2::let_assert;
+    use futures::{TryStreamExt as _, stream};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+    use crate::Model;
+
+    #[derive(Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
+
+    fn events(deltas: &[&str]) -> Vec<Result<Event, Error>> {
+        let mut events = vec![Ok(Event::MessageStarted {
+            id: String::from("message"),
+            model: Model::Gpt4_1,
+        })];
+        events.extend(deltas.iter().map(|delta| {
+            Ok(Event::TextDelta {
+                part_index: 0,
+                delta: (*delta).to_owned(),
+            })
+        }));
+        events.push(Ok(Event::Finished {
+            reason: FinishReason::Stop,
+        }));
+        events
+    }
+
+    #[tokio::test]
+    async fn streams_objects() {
+        let updates = updates::<Person>(stream::iter(events(&[
+            r#"{"name": "Al"#,
+            r#"ice", "#,
+            r#""age": 3"#,
+            "0}",
+        ])))
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("object to generate");
+        assert_eq!(
+            updates,
+            vec![
+                Update::Partial(json!({"name": "Al"})),
+                Update::Partial(json!({"name": "Alice"})),
+                Update::Partial(json!({"name": "Alice", "age": 3_i32})),
+                Update::Partial(json!({"name": "Alice", "age": 30_i32})),
+                Update::Complete(Person {
+                    name: String::from("Alice"),
+                    age: 30,
+                }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn strips_code_fences() {
+        let updates = updates::<Person>(stream::iter(events(&[
+            "Here is the person:\n```json\n{\"name\": \"Al",
+            "ice\", \"age\": 30}\n```\nLet me know",
+            " if you need more.",
+        ])))
+        .try_collect::<Vec<_>>()
+        .await
+        .expect("object to generate");
+        assert_eq!(
+            updates,
+            vec![
+                Update::Partial(json!({"name": "Al"})),
+                Update::Partial(json!({"name": "Alice", "age": 30_i32})),
+                Update::Complete(Person {
+                    name: String::from("Alice"),
+                    age: 30,
+                }),
+            ]
+        );
+        assert_eq!(json_text(r#"{"text": "```"}"#), r#"{"text": "```"}"#);
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_output() {
+        let result = updates::<Person>(stream::iter(events(&[r#"{"name": 1}"#])))
+            .try_collect::<Vec<_>>()
+            .await;
+        let_assert!(Err(Error::InvalidOutput(_)) = result);
+    }
+
+    #[tokio::test]
+    async fn deserializes_partial_objects() {
+        #[derive(Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+        enum Kind {
+            Article,
+            Video,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+        struct Card {
+            title: String,
+            kind: Kind,
+            tags: Vec<String>,
+            note: Option<String>,
+        }
+
+        #[derive(Debug, PartialEq, Eq, Deserialize, JsonSchema)]
+        struct Cards {
+            cards: Vec<Card>,
+        }
+
+        let card = |title: &str| Card {
+            title: title.to_owned(),
+            kind: Kind::Article,
+            tags: Vec::new(),
+            note: None,
+        };
+        let updates = stream::iter([
+            Ok(Update::Partial(json!({}))),
+            Ok(Update::Partial(json!({"cards": [{"title": "Ru"}]}))),
+            Ok(Update::Partial(
+                json!({"cards": [{"title": "Rust", "kind": "Article"}]}),
+            )),
+            Ok(Update::Complete(Cards {
+                cards: vec![card("Rust")],
+            })),
+        ]);
+        let partials = partial_objects(updates)
+            .try_collect::<Vec<_>>()
+            .await
+            .expect("objects to deserialize");
+        assert_eq!(
+            partials,
+            vec![
+                Partial {
+                    object: Cards { cards: Vec::new() },
+                    complete: false,
+                },
+                Partial {
+                    object: Cards {
+                        cards: vec![card("Rust")],
+                    },
+                    complete: false,
+                },
+                Partial {
+                    object: Cards {
+                        cards: vec![card("Rust")],
+                    },
+                    complete: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn makes_response_formats_strict() {
+        #[derive(Deserialize, JsonSchema)]
+        #[expect(dead_code, reason = "only the schema is used")]
+        struct Team<T> {
+            lead: Option<Person>,
+            #[serde(default)]
+            members: Vec<T>,
+        }
+
+        let format = config::ResponseFormat::json_schema::<Team<Person>>()
+            .expect("object to be a response format");
+        assert_eq!(format.name, "Team_for_Person");
+        assert_eq!(
+            format.schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "lead": {"anyOf": [{"$ref": "#/$defs/Person"}, {"type": "null"}]},
+                    "members": {"type": "array", "items": {"$ref": "#/$defs/Person"}},
+                },
+                "required": ["lead", "members"],
+                "additionalProperties": false,
+                "$defs": {
+                    "Person": {
+                        "type": "object",
+                        "properties": {
+                            "name": {"type": "string"},
+                            "age": {"type": "integer", "minimum": 0_i32},
+                        },
+                        "required": ["name", "age"],
+                        "additionalProperties": false,
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_non_object_response_formats() {
+        let_assert!(
+            Err(Error::InvalidConfig(_)) = config::ResponseFormat::json_schema::<Vec<Person>>()
+        );
+    }
+}
@@ -1,2 +1,35 @@
 mod async_openai;
 mod openai_responses;
+
+pub use self::async_openai::ChatCompletions;
+pub use self::openai_responses::Responses;
+
+use crate::config;
+
+/// Base URL of the official OpenAI API.
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// Builds a `POST` request to `path` of an OpenAI-compatible API.
+///
+/// Invalid header names or values are reported when the request is sent.
+fn post(client: &reqwest::Client, config: &config::Client, path: &str) -> reqwest::RequestBuilder {
+    let base_url = config.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+    let mut request = client.post(format!("{}{path}", base_url.trim_end_matches('/')));
+    if let Some(api_key) = config
+        .api_key
+        .clone()
+        .or_else(|| std::env::var("OPENAI_API_KEY").ok())
+    {
+        request = request.bearer_auth(api_key);
+    }
+    if let Some(ref organization) = config.organization {
+        request = request.header("OpenAI-Organization", organization);
+    }
+    if let Some(ref project) = config.project {
+        request = request.header("OpenAI-Project", project);
+    }
+    for (name, value) in &config.headers {
+        request = request.header(name, value);
+    }
+    request
+}
@@ -1,30 +1,254 @@
+use alloc::collections::{BTreeMap, btree_map};
+
 use async_openai::types::{
     ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
     ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestDeveloperMessage,
     ChatCompletionRequestDeveloperMessageArgs, ChatCompletionRequestMessage,
-    ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageArgs,
-    ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessage,
-    ChatCompletionRequestUserMessageArgs, ChatCompletionTool, ChatCompletionToolArgs,
-    ChatCompletionToolType, FunctionCall, FunctionObjectArgs,
+    ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestToolMessage,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestToolMessageContent,
+    ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageArgs,
+    ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+    ChatCompletionStreamOptions, ChatCompletionTool, ChatCompletionToolArgs,
+    ChatCompletionToolChoiceOption, ChatCompletionToolType, CreateChatCompletionRequestArgs,
+    CreateChatCompletionStreamResponse, FinishReason, FunctionCall, FunctionObjectArgs, ImageUrl,
+    ReasoningEffort, ResponseFormat, ResponseFormatJsonSchema,
+};
+use futures::{StreamExt as _, stream::BoxStream};
+use genawaiter::sync::Gen;
+
+use crate::{
+    Error, Message, Tool, Usage, config, message,
+    provider::{self, Provider},
+    util::server_sent_events,
 };
 
-use crate::{Message, Tool, message};
+/// Provider for the OpenAI Chat Completions API.
+///
+/// Works with any OpenAI-compatible endpoint configured through
+/// [`GenerateConfig::client`](crate::GenerateConfig::client).
+#[derive(Debug, Clone, Default)]
+pub struct ChatCompletions {
+    client: reqwest::Client,
+}
+
+impl ChatCompletions {
+    /// Creates a provider.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Provider for ChatCompletions {
+    fn stream<'request>(
+        &'request self,
+        request: provider::Request<'request>,
+    ) -> BoxStream<'request, Result<provider::Event, Error>> {
+        Gen::new(move |co| async move {
+            let thread = match request
+                .messages
+                .iter()
+                .cloned()
+                .map(Vec::<ChatCompletionRequestMessage>::try_from)
+                .collect::<Result<Vec<_>, _>>()
+            {
+                Ok(thread) => thread.into_iter().flatten().collect::<Vec<_>>(),
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+
+            let chat_request = CreateChatCompletionRequestArgs::default()
+                .model(request.config.model.to_string())
+                .messages(thread)
+                .tools(request.tools.values().map(Into::into).collect::<Vec<_>>())
+                .parallel_tool_calls(request.config.parallel_tool_calls)
+                .tool_choice(match request.config.tool_choice {
+                    config::ToolChoice::Auto => ChatCompletionToolChoiceOption::Auto,
+                    config::ToolChoice::Required => ChatCompletionToolChoiceOption::Required,
+                    config::ToolChoice::None => ChatCompletionToolChoiceOption::None,
+                })
+                .stream_options(ChatCompletionStreamOptions {
+                    include_usage: true,
+                })
+                .build();
+            let mut chat_request = match chat_request {
+                Ok(chat_request) => chat_request,
+                Err(error) => {
+                    co.yield_(Err(Error::Other(error.to_string()))).await;
+                    return;
+                }
+            };
+            chat_request.max_completion_tokens = request.config.max_output_tokens;
+            chat_request.reasoning_effort =
+                request.config.reasoning.effort.map(|effort| match effort {
+                    config::ReasoningEffort::Low => ReasoningEffort::Low,
+                    config::ReasoningEffort::Medium => ReasoningEffort::Medium,
+                    config::ReasoningEffort::High => ReasoningEffort::High,
+                });
+            chat_request.response_format =
+                request
+                    .config
+                    .response_format
+                    .clone()
+                    .map(|format| ResponseFormat::JsonSchema {
+                        json_schema: ResponseFormatJsonSchema {
+                            description: None,
+                            name: format.name,
+                            schema: Some(format.schema),
+                            strict: Some(true),
+                        },
+                    });
+            chat_request.stream = Some(true);
+
+            let mut stream = server_sent_events(
+                super::post(&self.client, &request.config.client, "/chat/completions")
+                    .json(&chat_request),
+            );
+
+            let mut blocks = 0_usize;
+            let mut text_index = None;
+            let mut tool_indices = BTreeMap::new();
+            let mut incomplete = None;
+
+            while let Some(result) = stream.next().await {
+                let data = match result {
+                    Ok(data) => data,
+                    Err(error) => {
+                        co.yield_(Err(error)).await;
+                        return;
+                    }
+                };
+                if data == "[DONE]" {
+                    break;
+                }
+                let response =
+                    match serde_json::from_str::<CreateChatCompletionStreamResponse>(&data) {
+                        Ok(response) => response,
+                        Err(error) => {
+                            co.yield_(Err(error.into())).await;
+                            return;
+                        }
+                    };
+
+                if let Some(chat_choice) = response.choices.into_iter().next() {
+                    incomplete = match chat_choice.finish_reason {
+                        Some(FinishReason::Length) => Some(crate::FinishReason::Length),
+                        Some(FinishReason::ContentFilter) => {
+                            Some(crate::FinishReason::ContentFilter)
+                        }
+                        Some(
+                            FinishReason::Stop
+                            | FinishReason::ToolCalls
+                            | FinishReason::FunctionCall,
+                        ) => None,
+                        None => incomplete,
+                    };
+                    if let Some(content) = chat_choice.delta.content {
+                        let index = *text_index.get_or_insert_with(|| {
+                            let index = blocks;
+                            blocks = blocks.saturating_add(1);
+                            index
+                        });
+                        co.yield_(Ok(provider::Event::TextDelta {
+                            index,
+                            delta: content,
+                        }))
+                        .await;
+                    }
+
+                    for tool_call_delta in chat_choice.delta.tool_calls.unwrap_or_default() {
+                        let (name, arguments) = tool_call_delta
+                            .function
+                            .map_or((None, None), |function| (function.name, function.arguments));
+                        let index = match tool_indices.entry(tool_call_delta.index) {
+                            btree_map::Entry::Vacant(vacant_entry) => {
+                                // Text after a tool call starts a new text block.
+                                text_index = None;
+                                let index = *vacant_entry.insert(blocks);
+                                blocks = blocks.saturating_add(1);
+                                co.yield_(Ok(provider::Event::ToolCallStarted {
+                                    index,
+                                    id: tool_call_delta.id.unwrap_or_default(),
+                                    name: name.unwrap_or_default(),
+                                }))
+                                .await;
+                                index
+                            }
+                            btree_map::Entry::Occupied(occupied_entry) => *occupied_entry.get(),
+                        };
+                        if let Some(arguments) = arguments.filter(|arguments| !arguments.is_empty())
+                        {
+                            co.yield_(Ok(provider::Event::ToolArgsDelta {
+                                index,
+                                delta: arguments,
+                            }))
+                            .await;
+                        }
+                    }
+                }
+
+                if let Some(completion_usage) = response.usage {
+                    let prompt_details = completion_usage.prompt_tokens_details;
+                    let completion_details = completion_usage.completion_tokens_details;
+                    let cached_input_tokens = prompt_details
+                        .as_ref()
+                        .and_then(|details| details.cached_tokens)
+                        .unwrap_or_default();
+                    co.yield_(Ok(provider::Event::Usage(Usage {
+                        cached_input_tokens: cached_input_tokens.into(),
+                        input_tokens: completion_usage
+                            .prompt_tokens
+                            .saturating_sub(cached_input_tokens)
+                            .into(),
+                        output_tokens: completion_usage.completion_tokens.into(),
+                        reasoning_tokens: completion_details
+                            .as_ref()
+                            .and_then(|details| details.reasoning_tokens)
+                            .unwrap_or_default()
+                            .into(),
+                        audio_input_tokens: prompt_details
+                            .and_then(|details| details.audio_tokens)
+                            .unwrap_or_default()
+                            .into(),
+                        audio_output_tokens: completion_details
+                            .and_then(|details| details.audio_tokens)
+                            .unwrap_or_default()
+                            .into(),
+                    })))
+                    .await;
+                }
+            }
+
+            // The arguments of tool calls in a cut off response are incomplete,
+            // so the calls must not be executed.
+            if let Some(reason) = incomplete {
+                co.yield_(Ok(provider::Event::Incomplete(reason))).await;
+                return;
+            }
+            // Chat completions only mark the end of tool calls by ending the stream.
+            for index in tool_indices.into_values() {
+                co.yield_(Ok(provider::Event::ToolCallDone { index })).await;
+            }
+        })
+        .boxed()
+    }
+}
 
 /// Conversion from `Message` to a list of OpenAI-compatible chat completion request messages.
 impl TryFrom<Message> for Vec<ChatCompletionRequestMessage> {
-    type Error = anyhow::Error;
+    type Error = Error;
 
     fn try_from(val: Message) -> Result<Self, Self::Error> {
         let mut messages = Self::new();
         let mut tool_messages = Vec::new();
+        // Tool messages only hold text, so images follow them in a user message.
+        let mut attachments = Vec::new();
         for part in val.parts {
             match part {
                 message::Part::Text(text_part) => {
-                    if !tool_messages.is_empty() {
-                        for tool_message in core::mem::take(&mut tool_messages) {
-                            messages.push(ChatCompletionRequestMessage::Tool(tool_message));
-                        }
-                    }
+                    push_tool_messages(&mut messages, &mut tool_messages, &mut attachments);
                     messages.push(match val.role {
                         message::Role::Developer => {
                             ChatCompletionRequestMessage::Developer(text_part.into())
@@ -37,7 +261,12 @@ impl TryFrom<Message> for Vec<ChatCompletionRequestMessage> {
                 }
                 message::Part::Tool(tool_part) => {
                     if val.role != message::Role::Assistant {
-                        return Err(anyhow::anyhow!("Tool part must be an assistant message"));
+                        return Err(Error::InvalidMessages(String::from(
+                            "Tool part must be an assistant message",
+                        )));
+                    }
+                    if let Some(ref output) = tool_part.tool.result {
+                        attachments.extend(self::attachments(output.clone())?);
                     }
                     let (tool_call, tool_message) = tool_part.into();
 
@@ -54,7 +283,8 @@ impl TryFrom<Message> for Vec<ChatCompletionRequestMessage> {
                         messages.push(ChatCompletionRequestMessage::Assistant(
                             ChatCompletionRequestAssistantMessageArgs::default()
                                 .tool_calls(vec![tool_call])
-                                .build()?,
+                                .build()
+                                .map_err(|error| Error::InvalidMessages(error.to_string()))?,
                         ));
                     }
 
@@ -62,24 +292,33 @@ impl TryFrom<Message> for Vec<ChatCompletionRequestMessage> {
                         tool_messages.push(tool_message);
                     }
                 }
+                message::Part::Image(image_part) => {
+                    if val.role != message::Role::User {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Image part must be a user message",
+                        )));
+                    }
+                    messages.push(ChatCompletionRequestMessage::User(image_part.into()));
+                }
+                message::Part::File(_) => {
+                    return Err(Error::InvalidMessages(String::from(
+                        "The Chat Completions API does not accept file parts",
+                    )));
+                }
+                // The Chat Completions API does not accept reasoning.
+                message::Part::Reasoning(_) => {}
                 message::Part::Error(error_part) => {
                     if val.role != message::Role::Developer {
-                        return Err(anyhow::anyhow!("Error part must be a developer message"));
-                    }
-                    if !tool_messages.is_empty() {
-                        for tool_message in core::mem::take(&mut tool_messages) {
-                            messages.push(ChatCompletionRequestMessage::Tool(tool_message));
-                        }
+                        return Err(Error::InvalidMessages(String::from(
+                            "Error part must be a developer message",
+                        )));
                     }
+                    push_tool_messages(&mut messages, &mut tool_messages, &mut attachments);
                     messages.push(ChatCompletionRequestMessage::Developer(error_part.into()));
                 }
             }
         }
-        if !tool_messages.is_empty() {
-            for tool_message in core::mem::take(&mut tool_messages) {
-                messages.push(ChatCompletionRequestMessage::Tool(tool_message));
-            }
-        }
+        push_tool_messages(&mut messages, &mut tool_messages, &mut attachments);
         Ok(messages)
     }
 }
@@ -114,6 +353,61 @@ impl_from_text_part!(
     ChatCompletionRequestUserMessageArgs
 );
 
+/// Moves the pending tool messages to `messages`, followed by a user message
+/// with the images of their outputs.
+fn push_tool_messages(
+    messages: &mut Vec<ChatCompletionRequestMessage>,
+    tool_messages: &mut Vec<ChatCompletionRequestToolMessage>,
+    attachments: &mut Vec<ChatCompletionRequestUserMessageContentPart>,
+) {
+    messages.extend(
+        tool_messages
+            .drain(..)
+            .map(ChatCompletionRequestMessage::Tool),
+    );
+    if !attachments.is_empty() {
+        messages.push(ChatCompletionRequestMessage::User(
+            ChatCompletionRequestUserMessageContent::Array(core::mem::take(attachments)).into(),
+        ));
+    }
+}
+
+/// Returns the images of a tool call's output, which tool messages cannot hold.
+fn attachments(
+    output: message::ToolOutput,
+) -> Result<Vec<ChatCompletionRequestUserMessageContentPart>, Error> {
+    output
+        .into_content()
+        .into_iter()
+        .filter_map(|content| match content {
+            message::ToolContent::Image(image_part) => Some(Ok(image_part.into())),
+            message::ToolContent::File(_) => Some(Err(Error::InvalidMessages(String::from(
+                "The Chat Completions API does not accept file parts",
+            )))),
+            message::ToolContent::Text { .. } => None,
+        })
+        .collect()
+}
+
+/// Conversion from `ImagePart` to an OpenAI user message.
+impl From<message::ImagePart> for ChatCompletionRequestUserMessage {
+    fn from(val: message::ImagePart) -> Self {
+        ChatCompletionRequestUserMessageContent::Array(vec![val.into()]).into()
+    }
+}
+
+/// Conversion from `ImagePart` to an OpenAI user message content part.
+impl From<message::ImagePart> for ChatCompletionRequestUserMessageContentPart {
+    fn from(val: message::ImagePart) -> Self {
+        Self::ImageUrl(ChatCompletionRequestMessageContentPartImage {
+            image_url: ImageUrl {
+                url: val.source.to_url(),
+                detail: None,
+            },
+        })
+    }
+}
+
 /// Conversion from `ToolPart` to OpenAI tool call and optional tool message.
 impl From<message::ToolPart>
     for (
@@ -134,6 +428,7 @@ impl From<message::ToolCall>
     )
 {
     fn from(val: message::ToolCall) -> Self {
+        let output = val.result.as_ref().map(message::ToolOutput::to_text);
         (
             ChatCompletionMessageToolCall {
                 id: val.id.clone(),
@@ -143,13 +438,11 @@ impl From<message::ToolCall>
                     arguments: val.args.to_string(),
                 },
             },
-            if let Some(result) = val.result {
+            if let Some(output) = output {
                 Some(
                     ChatCompletionRequestToolMessageArgs::default()
                         .tool_call_id(val.id)
-                        .content(ChatCompletionRequestToolMessageContent::Text(
-                            result.to_string(),
-                        ))
+                        .content(ChatCompletionRequestToolMessageContent::Text(output))
                         .build()
                         .expect("failed to build tool message"),
                 )
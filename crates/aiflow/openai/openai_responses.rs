@@ -1,20 +1,555 @@
+use alloc::collections::BTreeMap;
+
+use futures::{StreamExt as _, stream::BoxStream};
+use genawaiter::sync::Gen;
 use openai_responses::types::{
-    ContentInput, FunctionCall, FunctionCallOutput, InputItem, InputListItem, InputMessage, Role,
-    Tool as ResponsesTool,
+    ContentInput, ContentItem, Event, FunctionCall, FunctionCallOutput, ImageDetail, Input,
+    InputItem, InputListItem, InputMessage, OutputContent, OutputItem, ReasoningSummary, Request,
+    Response, Role, TextConfig, TextFormat, Tool as ResponsesTool, ToolChoice,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{
+    ApiError, Error, FinishReason, Message, Tool, Usage, config, message,
+    provider::{self, Provider},
+    util::server_sent_events,
 };
 
-use crate::{Message, Tool, message};
+/// Name of the provider recorded in the reasoning parts it produces.
+const PROVIDER: &str = "openai";
+
+/// Provider for the OpenAI Responses API.
+///
+/// Works with any OpenAI-compatible endpoint configured through
+/// [`GenerateConfig::client`](crate::GenerateConfig::client).
+#[derive(Debug, Clone, Default)]
+pub struct Responses {
+    client: reqwest::Client,
+}
+
+impl Responses {
+    /// Creates a provider.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Provider for Responses {
+    fn stream<'request>(
+        &'request self,
+        request: provider::Request<'request>,
+    ) -> BoxStream<'request, Result<provider::Event, Error>> {
+        Gen::new(move |co| async move {
+            let (previous_response_id, thread) = match input(&request) {
+                Ok(input) => input,
+                Err(error) => {
+                    co.yield_(Err(error)).await;
+                    return;
+                }
+            };
+
+            let mut responses_request =
+                Request::builder()
+                    .model(request.config.model.to_string())
+                    .input(Input::List(Vec::new()))
+                    .previous_response_id_optional(previous_response_id)
+                    .store_optional(request.config.store)
+                    .tools(request.tools.values().map(Into::into).collect::<Vec<_>>())
+                    .tool_choice(match request.config.tool_choice {
+                        config::ToolChoice::Auto => ToolChoice::Auto,
+                        config::ToolChoice::Required => ToolChoice::Required,
+                        config::ToolChoice::None => ToolChoice::None,
+                    })
+                    .parallel_tool_calls(request.config.parallel_tool_calls)
+                    .max_output_tokens_optional(request.config.max_output_tokens.map(u64::from))
+                    .text_optional(request.config.response_format.clone().map(|format| {
+                        TextConfig {
+                            format: TextFormat::JsonSchema {
+                                schema: format.schema,
+                                description: String::new(),
+                                name: format.name,
+                                strict: Some(true),
+                            },
+                        }
+                    }))
+                    .build();
+            responses_request.stream = Some(true);
+
+            // `openai_responses` does not support encrypted reasoning, so the
+            // input and reasoning settings are set on the JSON body.
+            let mut body = match serde_json::to_value(&responses_request) {
+                Ok(body) => body,
+                Err(error) => {
+                    co.yield_(Err(error.into())).await;
+                    return;
+                }
+            };
+            if let Some(body) = body.as_object_mut() {
+                body.insert(String::from("input"), json!(thread));
+                let reasoning = &request.config.reasoning;
+                if *reasoning != config::Reasoning::default() {
+                    body.insert(String::from("reasoning"), json!(reasoning));
+                }
+                if request.config.store == Some(false) && reasons(request.config) {
+                    body.insert(
+                        String::from("include"),
+                        json!(["reasoning.encrypted_content"]),
+                    );
+                }
+            }
+
+            let mut stream = server_sent_events(
+                super::post(&self.client, &request.config.client, "/responses").json(&body),
+            );
+
+            let mut blocks = BTreeMap::new();
+
+            while let Some(result) = stream.next().await {
+                let data = match result {
+                    Ok(data) => data,
+                    Err(error) => {
+                        co.yield_(Err(error)).await;
+                        return;
+                    }
+                };
+                if let Ok(FailureEvent::Failed { response }) =
+                    serde_json::from_str::<FailureEvent>(&data)
+                {
+                    co.yield_(Err(ApiError::from(response.error).into())).await;
+                    return;
+                }
+                if let Ok(event) = serde_json::from_str::<ReasoningEvent>(&data) {
+                    let event = match event {
+                        ReasoningEvent::ItemAdded {
+                            output_index,
+                            item: ReasoningOutput::Reasoning { id, .. },
+                        } => {
+                            let index = blocks.len();
+                            blocks.insert((output_index, 0), index);
+                            Ok(provider::Event::ReasoningStarted {
+                                index,
+                                id,
+                                provider: String::from(PROVIDER),
+                            })
+                        }
+                        ReasoningEvent::ItemDone {
+                            output_index,
+                            item:
+                                ReasoningOutput::Reasoning {
+                                    encrypted_content, ..
+                                },
+                        } => block(&blocks, output_index, 0).map(|index| {
+                            provider::Event::ReasoningDone {
+                                index,
+                                encrypted_content,
+                            }
+                        }),
+                        // Summary parts are separated like paragraphs.
+                        ReasoningEvent::SummaryPartAdded {
+                            output_index,
+                            summary_index,
+                        } => {
+                            if summary_index == 0 {
+                                continue;
+                            }
+                            block(&blocks, output_index, 0).map(|index| {
+                                provider::Event::ReasoningDelta {
+                                    index,
+                                    delta: String::from("\n\n"),
+                                }
+                            })
+                        }
+                        ReasoningEvent::SummaryTextDelta {
+                            output_index,
+                            delta,
+                        } => block(&blocks, output_index, 0)
+                            .map(|index| provider::Event::ReasoningDelta { index, delta }),
+                    };
+                    let failed = event.is_err();
+                    co.yield_(event).await;
+                    if failed {
+                        return;
+                    }
+                    continue;
+                }
+
+                let event = match serde_json::from_str::<Event>(&data) {
+                    Ok(event) => event,
+                    Err(error) => {
+                        // Events that are newer than `openai_responses` are
+                        // skipped unless the output depends on them.
+                        if serde_json::from_str::<Envelope>(&data)
+                            .is_ok_and(|envelope| !HANDLED_EVENTS.contains(&envelope.kind.as_str()))
+                        {
+                            continue;
+                        }
+                        co.yield_(Err(error.into())).await;
+                        return;
+                    }
+                };
+
+                #[expect(clippy::wildcard_enum_match_arm, reason = "there are a lot of events")]
+                let event = match event {
+                    Event::ResponseCompleted { response }
+                    | Event::ResponseIncomplete { response } => {
+                        for event in finished(response, request.config.store) {
+                            co.yield_(Ok(event)).await;
+                        }
+                        continue;
+                    }
+                    Event::OutputItemAdded {
+                        item: OutputItem::FunctionCall(function_call),
+                        output_index,
+                    } => {
+                        let index = blocks.len();
+                        blocks.insert((output_index, 0), index);
+                        co.yield_(Ok(provider::Event::ToolCallStarted {
+                            index,
+                            id: function_call.call_id,
+                            name: function_call.name,
+                        }))
+                        .await;
+                        if function_call.arguments.is_empty() {
+                            continue;
+                        }
+                        Ok(provider::Event::ToolArgsDelta {
+                            index,
+                            delta: function_call.arguments,
+                        })
+                    }
+                    Event::ContentPartAdded {
+                        part:
+                            OutputContent::Text { text, .. }
+                            | OutputContent::Refusal { refusal: text, .. },
+                        content_index,
+                        output_index,
+                        ..
+                    } => {
+                        let index = blocks.len();
+                        blocks.insert((output_index, content_index), index);
+                        Ok(provider::Event::TextDelta { index, delta: text })
+                    }
+                    Event::RefusalDelta {
+                        delta,
+                        content_index,
+                        output_index,
+                        ..
+                    }
+                    | Event::OutputTextDelta {
+                        delta,
+                        content_index,
+                        output_index,
+                        ..
+                    } => block(&blocks, output_index, content_index)
+                        .map(|index| provider::Event::TextDelta { index, delta }),
+                    Event::FunctionCallArgumentsDelta {
+                        delta,
+                        output_index,
+                        ..
+                    } => block(&blocks, output_index, 0)
+                        .map(|index| provider::Event::ToolArgsDelta { index, delta }),
+                    Event::FunctionCallArgumentsDone { output_index, .. } => {
+                        block(&blocks, output_index, 0)
+                            .map(|index| provider::Event::ToolCallDone { index })
+                    }
+                    Event::Error { code, message, .. } => {
+                        Err(ApiError::new(None, code, message).into())
+                    }
+                    // Failures with an error are handled before.
+                    Event::ResponseFailed { .. } => {
+                        Err(ApiError::new(None, None, String::from("Response failed")).into())
+                    }
+                    _ => {
+                        // Ignore other events
+                        continue;
+                    }
+                };
+                let failed = event.is_err();
+                co.yield_(event).await;
+                if failed {
+                    return;
+                }
+            }
+        })
+        .boxed()
+    }
+}
+
+/// Returns the events of a completed or incomplete response: its cursor, its
+/// usage and why it is incomplete.
+fn finished(response: Response, store: Option<bool>) -> Vec<provider::Event> {
+    let mut events = Vec::new();
+    if store != Some(false) {
+        events.push(provider::Event::Cursor(response.id));
+    }
+    if let Some(usage) = response.usage {
+        events.push(provider::Event::Usage(Usage {
+            cached_input_tokens: usage.input_tokens_details.cached_tokens.into(),
+            input_tokens: usage
+                .input_tokens
+                .saturating_sub(usage.input_tokens_details.cached_tokens)
+                .into(),
+            output_tokens: usage.output_tokens.into(),
+            reasoning_tokens: usage.output_tokens_details.reasoning_tokens.into(),
+            ..Usage::default()
+        }));
+    }
+    if let Some(details) = response.incomplete_details {
+        events.push(provider::Event::Incomplete(
+            if details.reason == "content_filter" {
+                FinishReason::ContentFilter
+            } else {
+                FinishReason::Length
+            },
+        ));
+    }
+    events
+}
+
+/// Types of the events that must be parsed for the output to be complete.
+const HANDLED_EVENTS: &[&str] = &[
+    "error",
+    "response.completed",
+    "response.failed",
+    "response.incomplete",
+    "response.function_call_arguments.delta",
+    "response.function_call_arguments.done",
+    "response.output_text.delta",
+    "response.refusal.delta",
+];
+
+/// The type of any event.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// The event of a failed response, whose error `openai_responses` drops.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum FailureEvent {
+    #[serde(rename = "response.failed")]
+    Failed { response: FailedResponse },
+}
+
+/// A failed response.
+#[derive(Debug, Deserialize)]
+struct FailedResponse {
+    error: ResponseError,
+}
+
+/// The error of a failed response.
+#[derive(Debug, Deserialize)]
+struct ResponseError {
+    code: Option<String>,
+    message: String,
+}
+
+impl From<ResponseError> for ApiError {
+    fn from(val: ResponseError) -> Self {
+        Self::new(None, val.code, val.message)
+    }
+}
+
+/// Events of reasoning items, which `openai_responses` does not support.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum ReasoningEvent {
+    #[serde(rename = "response.output_item.added")]
+    ItemAdded {
+        output_index: u64,
+        item: ReasoningOutput,
+    },
+    #[serde(rename = "response.output_item.done")]
+    ItemDone {
+        output_index: u64,
+        item: ReasoningOutput,
+    },
+    #[serde(rename = "response.reasoning_summary_part.added")]
+    SummaryPartAdded {
+        output_index: u64,
+        summary_index: u64,
+    },
+    #[serde(rename = "response.reasoning_summary_text.delta")]
+    SummaryTextDelta { output_index: u64, delta: String },
+}
+
+/// A reasoning output item. Other output items fail to parse.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ReasoningOutput {
+    Reasoning {
+        id: Option<String>,
+        encrypted_content: Option<String>,
+    },
+}
+
+/// A reasoning input item, including its encrypted content.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename = "reasoning")]
+struct ReasoningItem {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    summary: Vec<ReasoningSummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_content: Option<String>,
+}
+
+/// An input item of a request.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+enum Item {
+    /// An item supported by `openai_responses`.
+    Supported(InputListItem),
+    /// Reasoning of a previous response.
+    Reasoning(ReasoningItem),
+}
+
+impl From<InputListItem> for Item {
+    fn from(val: InputListItem) -> Self {
+        Self::Supported(val)
+    }
+}
+
+/// Returns whether the model of the configuration reasons.
+///
+/// Encrypted reasoning is only requested for reasoning models, because other
+/// models reject it. Models that are not known to reason or not are taken to
+/// reason if reasoning is configured.
+fn reasons(config: &crate::GenerateConfig) -> bool {
+    config
+        .model
+        .reasons()
+        .unwrap_or_else(|| config.reasoning != config::Reasoning::default())
+}
+
+/// Returns the index of the block at the given output and content index.
+fn block(
+    blocks: &BTreeMap<(u64, u64), usize>,
+    output_index: u64,
+    content_index: u64,
+) -> Result<usize, Error> {
+    blocks
+        .get(&(output_index, content_index))
+        .copied()
+        .ok_or_else(|| {
+            Error::UnexpectedEvent(format!(
+                "Delta for output {output_index}, content {content_index} before it was added"
+            ))
+        })
+}
+
+/// Returns the response to continue from and the input items of the request.
+///
+/// If the thread contains the message of the request's cursor, only the items
+/// that the previous response has not seen are sent: the results of the tool
+/// calls it made and all messages after it.
+fn input(request: &provider::Request<'_>) -> Result<(Option<String>, Vec<Item>), Error> {
+    let resumed = request
+        .cursor
+        .filter(|_| request.config.store != Some(false))
+        .and_then(|cursor| {
+            let position = request
+                .messages
+                .iter()
+                .position(|message| message.id == cursor.message_id)?;
+            let (seen, unseen) = request.messages.split_at(position.saturating_add(1));
+            Some((cursor, seen.last()?, unseen))
+        });
+
+    let Some((cursor, last_seen, unseen)) = resumed else {
+        let items = items(request.messages, request.config.store)?;
+        return Ok((None, items));
+    };
+
+    let mut items = Vec::new();
+    let mut attachments = Vec::new();
+    for part in last_seen.parts.iter().skip(cursor.part_index) {
+        match *part {
+            message::Part::Tool(ref tool_part) => {
+                let Some(ref output) = tool_part.tool.result else {
+                    continue;
+                };
+                items.push(function_call_output(tool_part.tool.id.clone(), output).into());
+                attachments.extend(self::attachments(output.clone())?);
+            }
+            message::Part::Text(_)
+            | message::Part::Image(_)
+            | message::Part::File(_)
+            | message::Part::Reasoning(_)
+            | message::Part::Error(_) => {}
+        }
+    }
+    if !attachments.is_empty() {
+        items.push(user_content(attachments));
+    }
+    items.extend(self::items(unseen, request.config.store)?);
+    Ok((Some(cursor.response_id.clone()), items))
+}
+
+/// Converts messages to a list of input items.
+///
+/// Without stored responses, reasoning can only be sent back with its
+/// encrypted content.
+fn items(messages: &[Message], store: Option<bool>) -> Result<Vec<Item>, Error> {
+    Ok(messages
+        .iter()
+        .cloned()
+        .map(Vec::<Item>::try_from)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .filter(|item| match *item {
+            Item::Reasoning(ref reasoning) => {
+                store != Some(false) || reasoning.encrypted_content.is_some()
+            }
+            Item::Supported(_) => true,
+        })
+        .collect())
+}
 
-/// Conversion from `Message` to a list of OpenAI-compatible `InputListItem`s.
-impl TryFrom<Message> for Vec<InputListItem> {
-    type Error = anyhow::Error;
+/// Returns the item holding the text of a tool call's output.
+fn function_call_output(call_id: String, output: &message::ToolOutput) -> InputListItem {
+    InputListItem::Item(InputItem::FunctionCallOutput(FunctionCallOutput {
+        call_id,
+        output: output.to_text(),
+        id: None,
+        status: None,
+    }))
+}
+
+/// Returns the images and files of a tool call's output, which function call
+/// outputs cannot hold.
+fn attachments(output: message::ToolOutput) -> Result<Vec<ContentItem>, Error> {
+    output
+        .into_content()
+        .into_iter()
+        .filter_map(|content| match content {
+            message::ToolContent::Image(image_part) => Some(Ok(image_item(&image_part))),
+            message::ToolContent::File(file_part) => Some(file_item(file_part)),
+            message::ToolContent::Text { .. } => None,
+        })
+        .collect()
+}
+
+/// Conversion from `Message` to a list of input items.
+impl TryFrom<Message> for Vec<Item> {
+    type Error = Error;
 
     fn try_from(val: Message) -> Result<Self, Self::Error> {
         let mut items = Self::new();
+        // Images and files of tool outputs follow the outputs in a user message.
+        let mut attachments = Vec::new();
         for part in val.parts {
+            if !attachments.is_empty() && !matches!(part, message::Part::Tool(_)) {
+                items.push(user_content(core::mem::take(&mut attachments)));
+            }
             match part {
                 message::Part::Text(text_part) => {
-                    items.push(match val.role {
+                    items.push(Item::Supported(match val.role {
                         message::Role::Developer => InputListItem::Message(InputMessage {
                             role: Role::Developer,
                             content: ContentInput::Text(text_part.text),
@@ -27,22 +562,70 @@ impl TryFrom<Message> for Vec<InputListItem> {
                             role: Role::Assistant,
                             content: ContentInput::Text(text_part.text),
                         }),
-                    });
+                    }));
+                }
+                message::Part::Image(image_part) => {
+                    if val.role != message::Role::User {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Image part must be a user message",
+                        )));
+                    }
+                    items.push(user_content(vec![image_item(&image_part)]));
+                }
+                message::Part::File(file_part) => {
+                    if val.role != message::Role::User {
+                        return Err(Error::InvalidMessages(String::from(
+                            "File part must be a user message",
+                        )));
+                    }
+                    items.push(user_content(vec![file_item(file_part)?]));
                 }
                 message::Part::Tool(tool_part) => {
                     if val.role != message::Role::Assistant {
-                        return Err(anyhow::anyhow!("Tool part must be an assistant message"));
+                        return Err(Error::InvalidMessages(String::from(
+                            "Tool part must be an assistant message",
+                        )));
+                    }
+                    if let Some(ref output) = tool_part.tool.result {
+                        attachments.extend(self::attachments(output.clone())?);
+                    }
+                    items.extend(
+                        Vec::<InputListItem>::from(tool_part)
+                            .into_iter()
+                            .map(Item::from),
+                    );
+                }
+                message::Part::Reasoning(reasoning_part) => {
+                    if val.role != message::Role::Assistant {
+                        return Err(Error::InvalidMessages(String::from(
+                            "Reasoning part must be an assistant message",
+                        )));
+                    }
+                    // Reasoning of other providers and reasoning that cannot be
+                    // referenced is dropped.
+                    if reasoning_part
+                        .provider
+                        .as_deref()
+                        .is_none_or(|provider| provider == PROVIDER)
+                        && (reasoning_part.id.is_some()
+                            || reasoning_part.encrypted_content.is_some())
+                    {
+                        items.push(Item::Reasoning(reasoning_part.into()));
                     }
-                    items.extend(Self::from(tool_part));
                 }
                 message::Part::Error(error_part) => {
                     if val.role != message::Role::Developer {
-                        return Err(anyhow::anyhow!("Error part must be a developer message"));
+                        return Err(Error::InvalidMessages(String::from(
+                            "Error part must be a developer message",
+                        )));
                     }
-                    items.push(error_part.into());
+                    items.push(Item::Supported(error_part.into()));
                 }
             }
         }
+        if !attachments.is_empty() {
+            items.push(user_content(attachments));
+        }
 
         Ok(items)
     }
@@ -66,21 +649,60 @@ impl From<message::ToolCall> for Vec<InputListItem> {
             status: None,
         }))];
 
-        if let Some(result) = val.result {
-            items.push(InputListItem::Item(InputItem::FunctionCallOutput(
-                FunctionCallOutput {
-                    call_id: val.id,
-                    output: result.to_string(),
-                    id: None,
-                    status: None,
-                },
-            )));
+        if let Some(ref output) = val.result {
+            items.push(function_call_output(val.id, output));
         }
 
         items
     }
 }
 
+/// Returns a user message with the given content items.
+const fn user_content(content: Vec<ContentItem>) -> Item {
+    Item::Supported(InputListItem::Message(InputMessage {
+        role: Role::User,
+        content: ContentInput::List(content),
+    }))
+}
+
+/// Returns the content item of an image.
+fn image_item(image_part: &message::ImagePart) -> ContentItem {
+    ContentItem::Image {
+        detail: ImageDetail::Auto,
+        file_id: None,
+        image_url: Some(image_part.source.to_url()),
+    }
+}
+
+/// Returns the content item of a file.
+fn file_item(file_part: message::FilePart) -> Result<ContentItem, Error> {
+    if let message::Source::Url { .. } = file_part.source {
+        return Err(Error::InvalidMessages(String::from(
+            "The Responses API only accepts base64 file parts",
+        )));
+    }
+    Ok(ContentItem::File {
+        file_data: Some(file_part.source.to_url()),
+        file_id: None,
+        filename: file_part.filename,
+    })
+}
+
+/// Conversion from `ReasoningPart` to a reasoning item.
+impl From<message::ReasoningPart> for ReasoningItem {
+    fn from(val: message::ReasoningPart) -> Self {
+        Self {
+            id: val.id,
+            summary: if val.text.is_empty() {
+                Vec::new()
+            } else {
+                vec![ReasoningSummary::Text { text: val.text }]
+            },
+            encrypted_content: val.encrypted_content,
+        }
+    }
+}
+
 /// Conversion from `ErrorPart` to an OpenAI-compatible `InputListItem`.
 impl From<message::ErrorPart> for InputListItem {
     fn from(val: message::ErrorPart) -> Self {
@@ -102,3 +724,417 @@ impl From<&Tool> for ResponsesTool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use assert2::let_assert;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{Cursor, GenerateConfig, Model};
+
+    fn message(id: &str, role: message::Role, parts: Vec<message::Part>) -> Message {
+        Message {
+            id: id.to_owned(),
+            role,
+            parts,
+            model: None,
+        }
+    }
+
+    fn text(text: &str) -> message::Part {
+        message::Part::Text(message::TextPart {
+            text: text.to_owned(),
+        })
+    }
+
+    fn input_json(
+        messages: &[Message],
+        cursor: Option<&Cursor>,
+        config: &GenerateConfig,
+    ) -> (Option<String>, Value) {
+        let tools = crate::tool::Set::default();
+        let (previous_response_id, items) = input(&provider::Request {
+            messages,
+            tools: &tools,
+            config,
+            cursor,
+        })
+        .expect("input to convert");
+        (
+            previous_response_id,
+            serde_json::to_value(items).expect("items to serialize"),
+        )
+    }
+
+    #[test]
+    fn sends_only_unseen_items() {
+        let messages = [
+            message("1", message::Role::User, vec![text("What is 1 + 2?")]),
+            message(
+                "2",
+                message::Role::Assistant,
+                vec![
+                    text("Let me add."),
+                    message::Part::Tool(message::ToolPart {
+                        tool: message::ToolCall {
+                            id: String::from("call"),
+                            name: String::from("add"),
+                            args: json!({"left": 1_i32, "right": 2_i32}),
+                            result: Some(json!(3_i32).into()),
+                        },
+                    }),
+                ],
+            ),
+            message("3", message::Role::User, vec![text("Thanks!")]),
+        ];
+        let unseen = json!([
+            {"type": "function_call_output", "id": null, "status": null, "call_id": "call", "output": "3"},
+            {"type": "message", "role": "user", "content": "Thanks!"},
+        ]);
+        let config = GenerateConfig::default();
+
+        // The response that called the tool has not seen its result.
+        let cursor = Cursor {
+            response_id: String::from("resp_1"),
+            message_id: String::from("2"),
+            part_index: 0,
+        };
+        assert_eq!(
+            input_json(&messages, Some(&cursor), &config),
+            (Some(String::from("resp_1")), unseen)
+        );
+
+        // A later response has already seen the result.
+        let cursor = Cursor {
+            part_index: 2,
+            ..cursor
+        };
+        assert_eq!(
+            input_json(&messages, Some(&cursor), &config),
+            (
+                Some(String::from("resp_1")),
+                json!([{"type": "message", "role": "user", "content": "Thanks!"}])
+            )
+        );
+
+        // Without stored responses or the cursor's message, everything is sent.
+        let (_, thread) = input_json(&messages, None, &config);
+        let stateless = GenerateConfig {
+            store: Some(false),
+            ..GenerateConfig::default()
+        };
+        assert_eq!(
+            input_json(&messages, Some(&cursor), &stateless),
+            (None, thread.clone())
+        );
+        assert_eq!(
+            input_json(&messages[..1], Some(&cursor), &config),
+            (
+                None,
+                json!([{"type": "message", "role": "user", "content": "What is 1 + 2?"}])
+            )
+        );
+        assert_eq!(thread.as_array().map(Vec::len), Some(5));
+    }
+
+    #[test]
+    fn reports_incomplete_responses() {
+        let event = serde_json::from_value::<Event>(json!({
+            "type": "response.incomplete",
+            "response": {
+                "id": "resp_1",
+                "object": "response",
+                "created_at": 1_700_000_000_i64,
+                "status": "incomplete",
+                "incomplete_details": {"reason": "max_output_tokens"},
+                "instructions": null,
+                "max_output_tokens": 16_u32,
+                "metadata": {},
+                "model": "gpt-4.1",
+                "output": [],
+                "parallel_tool_calls": false,
+                "previous_response_id": null,
+                "reasoning": {"effort": null, "generate_summary": null},
+                "service_tier": null,
+                "store": true,
+                "temperature": 1.0_f64,
+                "text": {"format": {"type": "text"}},
+                "tool_choice": "auto",
+                "tools": [],
+                "top_p": 1.0_f64,
+                "truncation": "disabled",
+                "usage": {
+                    "input_tokens": 10_u32,
+                    "input_tokens_details": {"cached_tokens": 0_u32},
+                    "output_tokens": 16_u32,
+                    "output_tokens_details": {"reasoning_tokens": 0_u32},
+                    "total_tokens": 26_u32,
+                },
+                "user": null,
+            },
+        }))
+        .expect("event to parse");
+        let_assert!(Event::ResponseIncomplete { response } = event);
+
+        let events = finished(response, None);
+        let [
+            provider::Event::Cursor(ref response_id),
+            provider::Event::Usage(ref usage),
+            provider::Event::Incomplete(reason),
+        ] = *events
+        else {
+            panic!("expected cursor, usage and reason, got {events:?}");
+        };
+        assert_eq!(response_id, "resp_1");
+        assert_eq!(usage.output_tokens, 16_u32.into());
+        assert_eq!(reason, FinishReason::Length);
+    }
+
+    #[test]
+    fn reports_response_errors() {
+        let_assert!(
+            Ok(FailureEvent::Failed { response }) = serde_json::from_value::<FailureEvent>(json!({
+                "type": "response.failed",
+                "sequence_number": 3_u32,
+                "response": {
+                    "id": "resp_1",
+                    "status": "failed",
+                    "error": {
+                        "code": "server_error",
+                        "message": "The server had an error.",
+                    },
+                },
+            }))
+        );
+        let error = ApiError::from(response.error);
+        assert_eq!(error.code.as_deref(), Some("server_error"));
+        assert_eq!(error.message, "The server had an error.");
+        assert_eq!(error.kind, crate::ApiErrorKind::Server);
+    }
+
+    #[test]
+    fn requests_reasoning_of_reasoning_models() {
+        let config = |model: Model, reasoning: config::Reasoning| GenerateConfig {
+            model,
+            reasoning,
+            ..GenerateConfig::default()
+        };
+        let effort = config::Reasoning {
+            effort: Some(config::ReasoningEffort::High),
+            ..config::Reasoning::default()
+        };
+        assert!(reasons(&config(Model::O3, config::Reasoning::default())));
+        assert!(!reasons(&config(Model::Gpt4_1, effort.clone())));
+        assert!(reasons(&config(Model::Other(String::from("o5")), effort)));
+        assert!(!reasons(&config(
+            Model::Other(String::from("gpt-5-chat")),
+            config::Reasoning::default()
+        )));
+    }
+
+    #[test]
+    fn parses_reasoning_events() {
+        let event = serde_json::from_value::<ReasoningEvent>(json!({
+            "type": "response.output_item.done",
+            "output_index": 0_u32,
+            "item": {
+                "type": "reasoning",
+                "id": "rs_1",
+                "summary": [{"type": "summary_text", "text": "Adding."}],
+                "encrypted_content": "secret",
+            },
+        }))
+        .expect("reasoning event to parse");
+        let_assert!(
+            ReasoningEvent::ItemDone {
+                item: ReasoningOutput::Reasoning {
+                    encrypted_content: Some(encrypted_content),
+                    ..
+                },
+                ..
+            } = event
+        );
+        assert_eq!(encrypted_content, "secret");
+
+        // Other items are left to `openai_responses`.
+        let_assert!(
+            Err(_) = serde_json::from_value::<ReasoningEvent>(json!({
+                "type": "response.output_item.added",
+                "output_index": 1_u32,
+                "item": {
+                    "type": "function_call",
+                    "id": "fc_1",
+                    "call_id": "call",
+                    "name": "add",
+                    "arguments": "",
+                    "status": "in_progress",
+                },
+            }))
+        );
+    }
+
+    #[test]
+    fn sends_reasoning_back() {
+        let reasoning = |encrypted_content: Option<&str>| {
+            message(
+                "1",
+                message::Role::Assistant,
+                vec![
+                    message::Part::Reasoning(message::ReasoningPart {
+                        text: String::from("Adding."),
+                        id: Some(String::from("rs_1")),
+                        encrypted_content: encrypted_content.map(ToOwned::to_owned),
+                        provider: Some(String::from(PROVIDER)),
+                    }),
+                    text("3"),
+                ],
+            )
+        };
+        let config = GenerateConfig::default();
+        let stateless = GenerateConfig {
+            store: Some(false),
+            ..GenerateConfig::default()
+        };
+
+        let (_, items) = input_json(&[reasoning(Some("secret"))], None, &stateless);
+        assert_eq!(
+            items.get(0),
+            Some(&json!({
+                "type": "reasoning",
+                "id": "rs_1",
+                "summary": [{"type": "summary_text", "text": "Adding."}],
+                "encrypted_content": "secret",
+            }))
+        );
+
+        // Stored reasoning is referenced by its identifier, but without
+        // stored responses it is dropped.
+        let (_, items) = input_json(&[reasoning(None)], None, &config);
+        assert_eq!(items.as_array().map(Vec::len), Some(2));
+        let (_, items) = input_json(&[reasoning(None)], None, &stateless);
+        assert_eq!(items.as_array().map(Vec::len), Some(1));
+
+        // The thinking of Claude models is signed for Anthropic only.
+        let thinking = message(
+            "1",
+            message::Role::Assistant,
+            vec![
+                message::Part::Reasoning(message::ReasoningPart {
+                    text: String::from("Adding."),
+                    id: None,
+                    encrypted_content: Some(String::from("signature")),
+                    provider: Some(String::from("anthropic")),
+                }),
+                text("3"),
+            ],
+        );
+        let (_, items) = input_json(&[thinking], None, &stateless);
+        assert_eq!(items.as_array().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    fn converts_images_and_files() {
+        let image = message::Part::Image(message::ImagePart {
+            source: message::Source::Base64 {
+                media_type: String::from("image/png"),
+                data: String::from("iVBORw0K"),
+            },
+        });
+        let file = |source| {
+            message::Part::File(message::FilePart {
+                source,
+                filename: Some(String::from("report.pdf")),
+            })
+        };
+        let pdf = message::Source::Base64 {
+            media_type: String::from("application/pdf"),
+            data: String::from("JVBERi0="),
+        };
+        let config = GenerateConfig::default();
+
+        let messages = [message("1", message::Role::User, vec![image, file(pdf)])];
+        assert_eq!(
+            input_json(&messages, None, &config).1,
+            json!([
+                {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{
+                        "type": "input_image",
+                        "detail": "auto",
+                        "file_id": null,
+                        "image_url": "data:image/png;base64,iVBORw0K",
+                    }],
+                },
+                {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{
+                        "type": "input_file",
+                        "file_data": "data:application/pdf;base64,JVBERi0=",
+                        "file_id": null,
+                        "filename": "report.pdf",
+                    }],
+                },
+            ])
+        );
+
+        let url = message::Source::Url {
+            url: String::from("https://example.com/report.pdf"),
+        };
+        let_assert!(
+            Err(Error::InvalidMessages(_)) =
+                items(&[message("1", message::Role::User, vec![file(url)])], None)
+        );
+    }
+
+    #[test]
+    fn attaches_tool_output_images() {
+        let screenshot = message::ToolOutput::Content {
+            content: vec![
+                message::ToolContent::Text {
+                    text: String::from("The page is blank."),
+                },
+                message::ToolContent::Image(message::ImagePart {
+                    source: message::Source::Url {
+                        url: String::from("https://example.com/screenshot.png"),
+                    },
+                }),
+            ],
+        };
+        let messages = [message(
+            "1",
+            message::Role::Assistant,
+            vec![
+                message::Part::Tool(message::ToolPart {
+                    tool: message::ToolCall {
+                        id: String::from("call"),
+                        name: String::from("screenshot"),
+                        args: json!({}),
+                        result: Some(screenshot),
+                    },
+                }),
+                text("The page is blank."),
+            ],
+        )];
+        assert_eq!(
+            input_json(&messages, None, &GenerateConfig::default()).1,
+            json!([
+                {"type": "function_call", "id": null, "status": null, "call_id": "call", "name": "screenshot", "arguments": "{}"},
+                {"type": "function_call_output", "id": null, "status": null, "call_id": "call", "output": "The page is blank."},
+                {
+                    "type": "message",
+                    "role": "user",
+                    "content": [{
+                        "type": "input_image",
+                        "detail": "auto",
+                        "file_id": null,
+                        "image_url": "https://example.com/screenshot.png",
+                    }],
+                },
+                {"type": "message", "role": "assistant", "content": "The page is blank."},
+            ])
+        );
+    }
+}
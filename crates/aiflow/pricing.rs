@@ -0,0 +1,257 @@
+//! Model prices and cost accounting.
+
+use alloc::collections::BTreeMap;
+use std::{path::Path, sync::LazyLock};
+
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{Model, PricingError, Usage};
+
+/// The built-in pricing table, shipped with the crate.
+static BUILTIN: LazyLock<PricingTable> = LazyLock::new(|| {
+    PricingTable::from_json(include_str!("pricing.json")).expect("built-in pricing to be valid")
+});
+
+/// Prices of a model in dollars per million tokens.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pricing {
+    /// Price per million input tokens.
+    pub input: BigDecimal,
+    /// Price per million cached input tokens.
+    pub cached_input: BigDecimal,
+    /// Price per million output tokens.
+    pub output: BigDecimal,
+    /// Price per million reasoning tokens. Defaults to the output price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<BigDecimal>,
+    /// Price per million audio input tokens. Defaults to the input price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_input: Option<BigDecimal>,
+    /// Price per million audio output tokens. Defaults to the output price.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_output: Option<BigDecimal>,
+}
+
+impl Pricing {
+    /// Returns the cost of the given usage.
+    #[must_use]
+    pub fn cost(&self, usage: &Usage) -> BigDecimal {
+        let mut cost = &self.input * &usage.input_tokens
+            + &self.cached_input * &usage.cached_input_tokens
+            + &self.output * &usage.output_tokens;
+        // Reasoning and audio tokens are included in the input and output
+        // tokens, so we only account for the difference in price.
+        if let Some(ref reasoning) = self.reasoning {
+            cost += (reasoning - &self.output) * &usage.reasoning_tokens;
+        }
+        if let Some(ref audio_input) = self.audio_input {
+            cost += (audio_input - &self.input) * &usage.audio_input_tokens;
+        }
+        if let Some(ref audio_output) = self.audio_output {
+            cost += (audio_output - &self.output) * &usage.audio_output_tokens;
+        }
+        cost / BigDecimal::from(1_000_000_u32)
+    }
+}
+
+/// Prices of models, indexed by model name.
+///
+/// The [`Default`] table contains the prices shipped with the crate. Since
+/// prices change, tables can also be loaded from JSON or TOML, e.g.
+///
+/// ```json
+/// { "gpt-4.1": { "input": "2", "cached_input": "0.5", "output": "8" } }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PricingTable {
+    models: BTreeMap<String, Pricing>,
+}
+
+impl PricingTable {
+    /// Returns an empty table.
+    #[must_use]
+    pub const fn empty() -> Self {
+        Self {
+            models: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the table shipped with the crate.
+    #[must_use]
+    pub fn builtin() -> &'static Self {
+        &BUILTIN
+    }
+
+    /// Parses a table from JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JSON is not a valid table.
+    pub fn from_json(json: &str) -> Result<Self, PricingError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Parses a table from TOML.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the TOML is not a valid table.
+    pub fn from_toml(toml: &str) -> Result<Self, PricingError> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Loads a table from a file. Files with a `.toml` extension are parsed as
+    /// TOML, all others as JSON.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid table.
+    pub fn from_file(path: &Path) -> Result<Self, PricingError> {
+        let contents = std::fs::read_to_string(path)?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+        {
+            Self::from_toml(&contents)
+        } else {
+            Self::from_json(&contents)
+        }
+    }
+
+    /// Returns the prices of the model with the given name, if known.
+    #[must_use]
+    pub fn get(&self, model: &str) -> Option<&Pricing> {
+        self.models.get(model)
+    }
+
+    /// Sets the prices of the model with the given name, returning the previous ones.
+    pub fn insert(&mut self, model: String, pricing: Pricing) -> Option<Pricing> {
+        self.models.insert(model, pricing)
+    }
+
+    /// Adds all prices of `other` to this table, overriding existing ones.
+    pub fn extend(&mut self, other: Self) {
+        self.models.extend(other.models);
+    }
+
+    /// Returns the prices of the given model.
+    ///
+    /// Custom models use their own prices. Models missing from the table are free.
+    #[must_use]
+    pub fn pricing(&self, model: &Model) -> Pricing {
+        if let Model::Custom { ref pricing, .. } = *model {
+            return pricing.clone();
+        }
+        self.get(&model.to_string()).cloned().unwrap_or_default()
+    }
+
+    /// Returns the cost of the given usage for the given model.
+    #[must_use]
+    pub fn cost(&self, model: &Model, usage: &Usage) -> BigDecimal {
+        self.pricing(model).cost(usage)
+    }
+}
+
+impl Default for PricingTable {
+    fn default() -> Self {
+        Self::builtin().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(value: &str) -> BigDecimal {
+        value.parse().expect("decimal to parse")
+    }
+
+    #[test]
+    fn prices_builtin_models() {
+        for model in [
+            Model::Gpt4_1,
+            Model::Gpt4_1Mini,
+            Model::Gpt4_1Nano,
+            Model::O3,
+            Model::O4Mini,
+            Model::ClaudeOpus4,
+            Model::ClaudeSonnet4,
+            Model::Claude3_5Haiku,
+        ] {
+            assert!(
+                PricingTable::builtin().get(&model.to_string()).is_some(),
+                "{model} has no built-in price"
+            );
+        }
+        assert_eq!(
+            PricingTable::builtin()
+                .get("o4-mini")
+                .map(|pricing| pricing.cached_input.clone()),
+            Some(decimal("0.275"))
+        );
+    }
+
+    #[test]
+    fn loads_toml() {
+        let table = PricingTable::from_toml(
+            r#"
+            ["gpt-4o"]
+            input = "2.5"
+            cached_input = "1.25"
+            output = 10
+            reasoning = 20
+            "#,
+        )
+        .expect("table to parse");
+
+        let usage = Usage {
+            input_tokens: BigDecimal::from(1_000_000_u32),
+            output_tokens: BigDecimal::from(1_000_000_u32),
+            reasoning_tokens: BigDecimal::from(500_000_u32),
+            ..Usage::default()
+        };
+        assert_eq!(
+            table.cost(&Model::Other(String::from("gpt-4o")), &usage),
+            decimal("17.5")
+        );
+        assert_eq!(
+            table.cost(&Model::Other(String::from("unknown")), &usage),
+            BigDecimal::from(0_u32)
+        );
+    }
+
+    #[test]
+    fn loads_json() {
+        let table = PricingTable::from_json(
+            r#"{ "o3": { "input": 2, "cached_input": 0.5, "output": "8" } }"#,
+        )
+        .expect("table to parse");
+        assert_eq!(
+            table.get("o3"),
+            Some(&Pricing {
+                input: decimal("2"),
+                cached_input: decimal("0.5"),
+                output: decimal("8"),
+                ..Pricing::default()
+            })
+        );
+    }
+
+    #[test]
+    fn fails_on_invalid_tables() {
+        assert!(matches!(
+            PricingTable::from_json(r#"{ "o3": { "input": "free" } }"#),
+            Err(PricingError::Json(_))
+        ));
+        assert!(matches!(
+            PricingTable::from_toml("[o3"),
+            Err(PricingError::Toml(_))
+        ));
+        assert!(matches!(
+            PricingTable::from_file(Path::new("missing.toml")),
+            Err(PricingError::Io(_))
+        ));
+    }
+}
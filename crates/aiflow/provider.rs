@@ -0,0 +1,101 @@
+//! Abstraction over model backends.
+//!
+//! A [`Provider`] turns a thread of [`Message`]s and a [`tool::Set`] into a
+//! normalized stream of [`Event`]s for a single model turn. The agent loop in
+//! [`provider_stream`](crate::provider_stream) is built on top of it, so new
+//! backends only need to implement the translation.
+
+use futures::stream::BoxStream;
+
+use crate::{Cursor, Error, FinishReason, GenerateConfig, Message, Usage, tool};
+
+/// A model backend that can stream a single turn of a conversation.
+pub trait Provider: Send + Sync {
+    /// Streams the model output for the given request as normalized events.
+    fn stream<'request>(
+        &'request self,
+        request: Request<'request>,
+    ) -> BoxStream<'request, Result<Event, Error>>;
+}
+
+/// A request for a single model turn.
+#[derive(Clone, Copy)]
+pub struct Request<'request> {
+    /// The conversation so far, including the partial assistant message.
+    pub messages: &'request [Message],
+    /// Tools available to the model.
+    pub tools: &'request tool::Set,
+    /// Configuration for the generation.
+    pub config: &'request GenerateConfig,
+    /// Cursor for the previous response, if any.
+    pub cursor: Option<&'request Cursor>,
+}
+
+/// A normalized event emitted by a [`Provider`].
+///
+/// Content blocks are identified by an `index` which is unique within a
+/// single turn. Providers assign indices in order of appearance.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// Text was appended to the text block at `index`.
+    TextDelta {
+        /// Index of the text block.
+        index: usize,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// A tool call was started at `index`.
+    ToolCallStarted {
+        /// Index of the tool call block.
+        index: usize,
+        /// Unique identifier for the tool call.
+        id: String,
+        /// Name of the tool being called.
+        name: String,
+    },
+    /// Arguments were appended to the tool call at `index`.
+    ToolArgsDelta {
+        /// Index of the tool call block.
+        index: usize,
+        /// The raw JSON fragment that was appended.
+        delta: String,
+    },
+    /// The arguments of the tool call at `index` are complete.
+    ToolCallDone {
+        /// Index of the tool call block.
+        index: usize,
+    },
+    /// Reasoning was started at `index`.
+    ReasoningStarted {
+        /// Index of the reasoning block.
+        index: usize,
+        /// The provider's identifier of the reasoning.
+        id: Option<String>,
+        /// Name of the provider, which is recorded in the
+        /// [reasoning part](crate::message::ReasoningPart::provider).
+        provider: String,
+    },
+    /// Summary text was appended to the reasoning at `index`.
+    ReasoningDelta {
+        /// Index of the reasoning block.
+        index: usize,
+        /// The text that was appended.
+        delta: String,
+    },
+    /// The reasoning at `index` is complete.
+    ReasoningDone {
+        /// Index of the reasoning block.
+        index: usize,
+        /// The encrypted reasoning, if the provider returned it.
+        encrypted_content: Option<String>,
+    },
+    /// Token usage for the turn.
+    Usage(Usage),
+    /// Identifier of the response that can be used to continue the
+    /// conversation. It becomes the [`Cursor`] of the session.
+    Cursor(String),
+    /// The response stopped before the model finished it, e.g. because it
+    /// reached [`GenerateConfig::max_output_tokens`]. The generation finishes
+    /// with the given reason after the turn.
+    Incomplete(FinishReason),
+}
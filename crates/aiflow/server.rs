@@ -0,0 +1,158 @@
+//! HTTP endpoint that streams generations as server-sent events.
+//!
+//! The [`handler`] accepts a JSON array of [`Message`]s, runs the agent loop
+//! with a fresh [`tool::Set`] and streams snapshots of the assistant message.
+//! Every snapshot is serialized while the message is locked and the lock is
+//! released before the event is sent, so slow clients never block the loop.
+
+use core::pin::pin;
+
+use axum::{
+    Json, Router,
+    extract::State,
+    response::sse::{self, KeepAlive, Sse},
+    routing::post,
+};
+use futures::{Stream, StreamExt as _};
+use genawaiter::sync::Gen;
+
+use crate::{CancellationToken, GenerateConfig, Message, Provider, Session, provider_stream, tool};
+
+/// State of the [`handler`].
+#[derive(Debug, Clone)]
+pub struct Chat<P, F> {
+    provider: P,
+    tools: F,
+    config: Option<GenerateConfig>,
+}
+
+impl<P, F> Chat<P, F>
+where
+    P: Provider + Clone + 'static,
+    F: Fn() -> tool::Set + Clone + Send + Sync + 'static,
+{
+    /// Creates the state from a provider and a factory for the tools of each request.
+    pub const fn new(provider: P, tools: F) -> Self {
+        Self {
+            provider,
+            tools,
+            config: None,
+        }
+    }
+
+    /// Sets the configuration used for every request.
+    #[must_use]
+    pub fn config(mut self, config: GenerateConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Returns a router that serves the [`handler`] on `POST /`.
+    pub fn into_router(self) -> Router {
+        Router::new()
+            .route("/", post(handler::<P, F>))
+            .with_state(self)
+    }
+}
+
+/// Streams the assistant message for the posted messages as server-sent events.
+///
+/// Every `message` event holds a snapshot of the assistant message. If the
+/// generation fails, an `error` event holds the error. The stream always ends
+/// with a `session` event holding the session, including its cost.
+pub async fn handler<P, F>(
+    State(chat): State<Chat<P, F>>,
+    Json(messages): Json<Vec<Message>>,
+) -> Sse<impl Stream<Item = Result<sse::Event, axum::Error>>>
+where
+    P: Provider + Clone + 'static,
+    F: Fn() -> tool::Set + Clone + Send + Sync + 'static,
+{
+    let Chat {
+        provider,
+        tools,
+        config,
+    } = chat;
+    let tools = tools();
+
+    let events = Gen::new(|co| async move {
+        // Cancels the generation when the client disconnects and the stream is dropped.
+        let cancellation = CancellationToken::new();
+        let _guard = cancellation.clone().drop_guard();
+        let mut session = Session::default();
+        {
+            let mut stream = pin!(provider_stream(
+                provider,
+                &mut session,
+                &messages,
+                tools,
+                config,
+                cancellation,
+            ));
+            while let Some(result) = stream.next().await {
+                match result {
+                    Ok(message) => {
+                        let event = sse::Event::default().event("message").json_data(&*message);
+                        drop(message);
+                        co.yield_(event).await;
+                    }
+                    Err(error) => {
+                        co.yield_(Ok(sse::Event::default()
+                            .event("error")
+                            .data(error.to_string())))
+                            .await;
+                        break;
+                    }
+                }
+            }
+        }
+        co.yield_(sse::Event::default().event("session").json_data(&session))
+            .await;
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse as _;
+    use futures::{stream, stream::BoxStream};
+
+    use super::*;
+    use crate::{Error, provider};
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Provider for Echo {
+        fn stream<'request>(
+            &'request self,
+            request: provider::Request<'request>,
+        ) -> BoxStream<'request, Result<provider::Event, Error>> {
+            let delta = format!("{} messages", request.messages.len());
+            stream::iter([
+                Ok(provider::Event::TextDelta { index: 0, delta }),
+                Err(Error::Other(String::from("Connection reset"))),
+            ])
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_server_sent_events() {
+        let chat = Chat::new(Echo, tool::Set::default);
+        let response = handler(State(chat), Json(Vec::new())).await.into_response();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body to be read");
+        let body = String::from_utf8(body.to_vec()).expect("body to be UTF-8");
+
+        let events: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(events, ["message", "message", "error", "session"]);
+        assert!(body.contains(r#""text":"0 messages""#), "{body}");
+        assert!(body.contains("data: Connection reset"), "{body}");
+    }
+}
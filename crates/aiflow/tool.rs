@@ -2,6 +2,7 @@ pub mod executor;
 pub mod extract;
 
 use core::any::Any;
+use core::time::Duration;
 
 use alloc::sync::Arc;
 
@@ -9,12 +10,13 @@ use derive_builder::Builder;
 use executor::Executor;
 use futures::{FutureExt as _, future::BoxFuture};
 use rustc_hash::FxHashMap;
-use schemars::Schema;
-use schemars::transform::RecursiveTransform;
-use schemars::{JsonSchema, schema_for, transform::Transform as _};
+use schemars::{JsonSchema, Schema};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+use crate::{message::ToolOutput, util::json_schema};
 
 /// Represents a callable tool that can be used by the AI, including its name, description, parameters, and execution logic.
 #[derive(Builder)]
@@ -44,6 +46,17 @@ pub struct Tool {
     /// Tools that support streaming will be called for each chunk of tool output.
     #[builder(default)]
     stream: bool,
+    /// Whether the tool must not run concurrently with other tools.
+    ///
+    /// Serial tools are executed one at a time, in the order they were called,
+    /// after all other tool calls of the same model turn completed.
+    #[builder(default)]
+    serial: bool,
+    /// Maximum duration of a single execution of the tool.
+    ///
+    /// A tool that times out is aborted and its result is a [`TimedOut`] error.
+    #[builder(setter(strip_option), default)]
+    timeout: Option<Duration>,
     /// Optional context for the tool.
     #[builder(setter(custom), default)]
     context: Option<Context>,
@@ -77,25 +90,60 @@ impl Tool {
         self.stream
     }
 
+    /// Returns true if the tool must not run concurrently with other tools.
+    #[must_use]
+    pub const fn is_serial(&self) -> bool {
+        self.serial
+    }
+
+    /// Returns the maximum duration of a single execution of the tool.
+    #[must_use]
+    pub const fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
     /// Executes the tool with the given id and arguments, if an executor is set.
+    ///
+    /// The tool can observe the `cancellation` token with the
+    /// [`Cancellation`](extract::Cancellation) extractor. The timeout starts
+    /// when the future is first polled, so queued executions are not charged
+    /// for the time they wait.
     #[must_use]
     pub fn execute(
         &self,
         id: String,
         args: Value,
-    ) -> Option<BoxFuture<'static, anyhow::Result<Value>>> {
+        cancellation: CancellationToken,
+    ) -> Option<BoxFuture<'static, anyhow::Result<ToolOutput>>> {
         self.execute.as_ref().map(|executor| {
-            executor.execute((Call {
+            let future = executor.execute((Call {
                 context: self.context.clone(),
                 id: Some(id),
                 args: Some(args),
-            },))
+                cancellation,
+            },));
+            let Some(timeout) = self.timeout else {
+                return future;
+            };
+            async move {
+                tokio::time::timeout(timeout, future)
+                    .await
+                    .unwrap_or_else(|_elapsed| Err(TimedOut(timeout).into()))
+            }
+            .boxed()
         })
     }
 }
 
+/// The error of a tool execution that exceeded the tool's timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Tool timed out after {}ms", .0.as_millis())]
+pub struct TimedOut(pub Duration);
+
 impl ToolBuilder {
     /// Sets the executor for the tool for automatic tooling.
+    ///
+    /// The result is serialized into a [`ToolOutput::Json`].
     #[expect(private_bounds, reason = "internal")]
     #[must_use]
     pub fn executor<R, F, A, E>(mut self, executor: E) -> Self
@@ -107,10 +155,31 @@ impl ToolBuilder {
     {
         self.execute = Some(Some(Arc::new(move |call_state| {
             let executor = executor.clone();
-            (async move |executor: E, mut call_state| -> Result<Value, anyhow::Error> {
+            (async move |executor: E, mut call_state| -> Result<ToolOutput, anyhow::Error> {
                 let args = A::try_from(&mut call_state)?;
                 let result = executor.execute(args).await?;
-                Ok(serde_json::to_value(result)?)
+                Ok(serde_json::to_value(result)?.into())
+            })(executor, call_state)
+            .boxed()
+        })));
+        self
+    }
+
+    /// Sets an executor that returns a [`ToolOutput`], e.g. to return images
+    /// or files.
+    #[expect(private_bounds, reason = "internal")]
+    #[must_use]
+    pub fn output_executor<F, A, E>(mut self, executor: E) -> Self
+    where
+        A: for<'re> TryFrom<&'re mut Call, Error = anyhow::Error> + Send,
+        F: Future<Output = anyhow::Result<ToolOutput>> + Send,
+        E: Executor<A, Output = F> + Clone + Sync + Send + 'static,
+    {
+        self.execute = Some(Some(Arc::new(move |call_state| {
+            let executor = executor.clone();
+            (async move |executor: E, mut call_state| -> Result<ToolOutput, anyhow::Error> {
+                let args = A::try_from(&mut call_state)?;
+                executor.execute(args).await
             })(executor, call_state)
             .boxed()
         })));
@@ -120,20 +189,7 @@ impl ToolBuilder {
     /// Sets the parameters schema for the tool using a type that implements `JsonSchema` and `DeserializeOwned`.
     #[must_use]
     pub fn parameters<P: JsonSchema + DeserializeOwned + Send>(mut self) -> Self {
-        let mut transform = RecursiveTransform(|schema: &mut Schema| {
-            if let Some(schema) = schema.as_object_mut() {
-                schema.remove("$schema");
-                schema.remove("title");
-                if schema.get("type").is_some() {
-                    schema.remove("format");
-                }
-            }
-        });
-
-        let mut parameter_schema = schema_for!(P);
-        transform.transform(&mut parameter_schema);
-
-        self.parameters = Some(parameter_schema);
+        self.parameters = Some(json_schema::<P>());
         self
     }
 
@@ -153,11 +209,13 @@ struct Call {
     context: Option<Context>,
     id: Option<String>,
     args: Option<Value>,
+    cancellation: CancellationToken,
 }
 
 /// Type alias for a callable tool executor.
-type CallExecutor =
-    Arc<dyn Executor<(Call,), Output = BoxFuture<'static, anyhow::Result<Value>>> + Sync + Send>;
+type CallExecutor = Arc<
+    dyn Executor<(Call,), Output = BoxFuture<'static, anyhow::Result<ToolOutput>>> + Sync + Send,
+>;
 
 /// A set of tools, indexed by name.
 pub type Set = FxHashMap<String, Tool>;
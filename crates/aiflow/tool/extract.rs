@@ -3,6 +3,7 @@ use alloc::sync::Arc;
 use core::any::Any;
 
 use serde::de::DeserializeOwned;
+use tokio_util::sync::CancellationToken;
 
 use super::Call;
 
@@ -12,9 +13,12 @@ pub struct Id(pub String);
 impl TryFrom<&mut Call> for Id {
     type Error = anyhow::Error;
 
-    #[expect(clippy::unwrap_in_result, reason = "Developer error")]
     fn try_from(state: &mut Call) -> Result<Self, Self::Error> {
-        Ok(Self(state.id.take().expect("id to exist")))
+        state
+            .id
+            .take()
+            .map(Self)
+            .ok_or_else(|| anyhow::anyhow!("Tool call id was already extracted"))
     }
 }
 
@@ -27,11 +31,12 @@ where
 {
     type Error = anyhow::Error;
 
-    #[expect(clippy::unwrap_in_result, reason = "Developer error")]
     fn try_from(state: &mut Call) -> Result<Self, Self::Error> {
-        serde_json::from_value(state.args.take().expect("args to exist"))
-            .map(Self)
-            .map_err(Into::into)
+        let args = state
+            .args
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Tool call arguments were already extracted"))?;
+        serde_json::from_value(args).map(Self).map_err(Into::into)
     }
 }
 
@@ -41,16 +46,29 @@ pub struct Context<T>(pub Arc<T>);
 impl<T: Any + Sync + Send> TryFrom<&mut Call> for Context<T> {
     type Error = anyhow::Error;
 
-    #[expect(clippy::unwrap_in_result, reason = "Developer error")]
     fn try_from(state: &mut Call) -> Result<Self, Self::Error> {
-        Ok(Self(
-            state
-                .context
-                .take()
-                .expect("context to exist")
-                .downcast()
-                .expect("to be a T"),
-        ))
+        let context = state
+            .context
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Tool has no context"))?;
+        context.downcast().map(Self).map_err(|_context| {
+            anyhow::anyhow!("Tool context is not a {}", core::any::type_name::<T>())
+        })
+    }
+}
+
+/// Wrapper for extracting the cancellation token of the generation.
+///
+/// The task of the tool is aborted when the generation is cancelled, so this
+/// is only needed to stop work the tool started outside of its task, e.g. a
+/// child process or a spawned task.
+pub struct Cancellation(pub CancellationToken);
+
+impl TryFrom<&mut Call> for Cancellation {
+    type Error = anyhow::Error;
+
+    fn try_from(state: &mut Call) -> Result<Self, Self::Error> {
+        Ok(Self(state.cancellation.clone()))
     }
 }
 
@@ -1,5 +1,16 @@
+use futures::{
+    StreamExt as _, future,
+    stream::{self, BoxStream},
+};
+use reqwest_eventsource::{Event, RequestBuilderExt as _, retry};
+use schemars::{
+    JsonSchema, Schema, schema_for,
+    transform::{RecursiveTransform, Transform as _},
+};
 use serde_json::Value;
 
+use crate::{ApiError, Error};
+
 /// Parses a possibly incomplete JSON string, attempting to repair and deserialize it into a `serde_json::Value`.
 ///
 /// # Arguments
@@ -15,6 +26,91 @@ pub fn parse_incomplete_json(input: &str) -> Result<Value, anyhow::Error> {
     Ok(serde_json::from_str(&value)?)
 }
 
+/// Generates the JSON schema of a type for tool parameters and structured
+/// outputs.
+///
+/// Keywords that models reject, such as `$schema`, `title` and `format`, are
+/// removed.
+pub fn json_schema<T: JsonSchema>() -> Schema {
+    let mut transform = RecursiveTransform(|schema: &mut Schema| {
+        if let Some(schema) = schema.as_object_mut() {
+            schema.remove("$schema");
+            schema.remove("title");
+            if schema.get("type").is_some() {
+                schema.remove("format");
+            }
+        }
+    });
+
+    let mut schema = schema_for!(T);
+    transform.transform(&mut schema);
+    schema
+}
+
+/// Makes a schema strict, as OpenAI's structured outputs require: objects do
+/// not allow additional properties and list all of their properties as
+/// required. Fields that may be omitted stay nullable, e.g. `Option`s.
+pub fn make_strict(schema: &mut Schema) {
+    let mut transform = RecursiveTransform(|schema: &mut Schema| {
+        let Some(schema) = schema.as_object_mut() else {
+            return;
+        };
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return;
+        };
+        let required = properties.keys().cloned().map(Value::String).collect();
+        schema.insert(String::from("required"), Value::Array(required));
+        schema.insert(String::from("additionalProperties"), Value::Bool(false));
+    });
+    transform.transform(schema);
+}
+
+/// Sends a request and streams the data of the server-sent events it responds with.
+///
+/// Reconnection is disabled since model requests are not idempotent. The
+/// stream ends after the first error.
+///
+/// # Arguments
+///
+/// * `request` - The request to send. Its body must not be a stream.
+///
+/// # Returns
+///
+/// A stream of event data, or the error that ended the stream.
+pub fn server_sent_events(
+    request: reqwest::RequestBuilder,
+) -> BoxStream<'static, Result<String, Error>> {
+    let mut event_source = match request.eventsource() {
+        Ok(event_source) => event_source,
+        Err(error) => {
+            return stream::once(future::ready(Err(Error::Other(error.to_string())))).boxed();
+        }
+    };
+    event_source.set_retry_policy(Box::new(retry::Never));
+    event_source
+        .filter_map(async |result| match result {
+            Ok(Event::Open) | Err(reqwest_eventsource::Error::StreamEnded) => None,
+            Ok(Event::Message(message)) => Some(Ok(message.data)),
+            Err(error) => Some(Err(stream_error(error).await)),
+        })
+        .boxed()
+}
+
+/// Converts an error of an event source into an [`Error`].
+async fn stream_error(error: reqwest_eventsource::Error) -> Error {
+    match error {
+        reqwest_eventsource::Error::InvalidStatusCode(_, response)
+        | reqwest_eventsource::Error::InvalidContentType(_, response) => {
+            ApiError::from_response(response).await.into()
+        }
+        reqwest_eventsource::Error::Transport(error) => error.into(),
+        reqwest_eventsource::Error::Utf8(error) => Error::Parse(error.to_string()),
+        reqwest_eventsource::Error::Parser(error) => Error::Parse(error.to_string()),
+        reqwest_eventsource::Error::InvalidLastEventId(_)
+        | reqwest_eventsource::Error::StreamEnded => Error::Other(error.to_string()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;